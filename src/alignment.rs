@@ -0,0 +1,102 @@
+use strum_macros::{Display, EnumString};
+
+/// How the recorded times relate to the print interval, selectable by name, e.g. `Alignment=Exact`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum Alignment {
+    /// Records the first timestep of each print interval at its own simulation time.
+    #[default]
+    Timestep,
+    /// Records at the exact multiples of the print interval from the simulation start,
+    /// interpolating linearly between the surrounding timesteps.
+    Exact,
+}
+
+/// Turns the values at the end of each timestep into rows at the exact print times
+/// `start + k * interval`.
+#[derive(Debug, Clone)]
+pub struct PrintAligner {
+    start: f64,
+    interval: f64,
+    /// Print times closer than this to a timestep take its values as they are
+    tolerance: f64,
+    next_print_no: u64,
+    previous: Option<(f64, Vec<f64>)>,
+}
+
+impl PrintAligner {
+    pub fn new(start: f64, interval: f64, tolerance: f64) -> Self {
+        PrintAligner {
+            start,
+            interval,
+            tolerance,
+            next_print_no: 0,
+            previous: None,
+        }
+    }
+
+    /// The next print time, computed from the start so rounding errors do not add up.
+    fn next_print_time(&self) -> f64 {
+        self.start + self.next_print_no as f64 * self.interval
+    }
+
+    /// The rows of all print times up to the timestep ending at `time` with the `values`.
+    pub fn rows(&mut self, time: f64, values: Vec<f64>) -> Vec<(f64, Vec<f64>)> {
+        let mut rows = vec![];
+        loop {
+            let print_time = self.next_print_time();
+            if print_time > time + self.tolerance {
+                break;
+            }
+            if (print_time - time).abs() <= self.tolerance {
+                rows.push((print_time, values.clone()));
+            } else if let Some((previous_time, previous_values)) = self.previous.as_ref() {
+                let weight = (print_time - previous_time) / (time - previous_time);
+                let interpolated = previous_values
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(a, b)| a + (b - a) * weight)
+                    .collect();
+                rows.push((print_time, interpolated));
+            }
+            // print times before the first timestep have no values
+            self.next_print_no += 1;
+        }
+        self.previous = Some((time, values));
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_print_times() {
+        // timestep of 0.4 h, print interval of 0.5 h
+        let mut aligner = PrintAligner::new(0.0, 0.5, 1e-6);
+        assert_eq!(aligner.rows(0.0, vec![0.0]), vec![(0.0, vec![0.0])]);
+        assert!(aligner.rows(0.4, vec![4.0]).is_empty());
+        assert_eq!(aligner.rows(0.8, vec![8.0]), vec![(0.5, vec![5.0])]);
+        // floating-point noise in the simulation time does not skip or double a row
+        let rows = aligner.rows(1.2000000000000002, vec![12.0]);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, 1.0);
+        assert!((rows[0].1[0] - 10.0).abs() < 1e-9);
+        assert_eq!(
+            aligner.rows(1.4999999999999998, vec![15.0]),
+            vec![(1.5, vec![15.0])]
+        );
+        assert!(aligner.rows(1.6, vec![16.0]).is_empty());
+    }
+
+    #[test]
+    fn test_several_print_times_per_timestep() {
+        let mut aligner = PrintAligner::new(10.0, 0.25, 1e-6);
+        assert_eq!(aligner.rows(10.0, vec![0.0]).len(), 1);
+        assert_eq!(
+            aligner.rows(10.5, vec![1.0]),
+            vec![(10.25, vec![0.5]), (10.5, vec![1.0])]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use crate::database::column::MetaCol;
+use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::odbc::OdbcProvider;
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use indexmap::IndexSet;
+use odbc_api::IntoParameter;
+use tracing::info;
+
+/// Table holding the files of each run, e.g. the deck and the listing.
+pub const ARTIFACT_TABLE: &str = "Artifacts";
+
+/// A file of a run, stored as its raw bytes, as decks and listings are often not UTF-8.
+pub struct Artifact {
+    /// What the file is, e.g. `Deck`
+    pub kind: String,
+    pub file_name: String,
+    pub content: Vec<u8>,
+}
+
+/// Adds the files of the run of the variant started at `run_start` to [`ARTIFACT_TABLE`].
+/// Files of earlier runs are kept.
+pub fn store_artifacts(
+    provider: &dyn OdbcProvider<'_>,
+    variant_name: &str,
+    run_start: &str,
+    artifacts: Vec<Artifact>,
+) -> Result<(), TrnSysError> {
+    provider.ensure_plain_table(ARTIFACT_TABLE, artifact_col_defs(), None)?;
+    for artifact in artifacts {
+        info!(
+            "Storing {} {} ({} bytes) in {}",
+            artifact.kind,
+            artifact.file_name,
+            artifact.content.len(),
+            ARTIFACT_TABLE
+        );
+        let mut cols = vec![
+            (MetaCol::Variant.as_str().to_string(), variant_name.into()),
+            ("RunStart".to_string(), run_start.into()),
+            ("Kind".to_string(), artifact.kind.into()),
+            ("FileName".to_string(), artifact.file_name.into()),
+        ]
+        .into_iter()
+        .map(|(name, value): (String, DbValue)| (name, value.to_parameter()))
+        .collect::<Vec<_>>();
+        cols.push((
+            "Content".to_string(),
+            Box::new(artifact.content.into_parameter()),
+        ));
+        provider.insert_data(ARTIFACT_TABLE, cols)?;
+    }
+    Ok(())
+}
+
+/// The columns of the artifact table.
+fn artifact_col_defs() -> IndexSet<ColDef> {
+    IndexSet::from([
+        MetaCol::Variant.col_def(),
+        ColDef::new("RunStart", ColDataType::Text, true, true),
+        ColDef::new("Kind", ColDataType::Text, true, true),
+        ColDef::new("FileName", ColDataType::Text, false, false),
+        ColDef::new("Content", ColDataType::Binary, false, false),
+    ])
+}
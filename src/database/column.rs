@@ -1,4 +1,6 @@
 use crate::database::datatype::{ColDataType, ColDef};
+use indexmap::IndexSet;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 #[derive(Debug, EnumIter, Eq, Hash, PartialEq, Clone, Ord, PartialOrd)]
@@ -25,3 +27,75 @@ impl MetaCol {
         }
     }
 }
+
+/// Optional integer column numbering the records of a variant, see [`step_col_defs`].
+pub const STEP_COL: &str = "Step";
+
+/// The columns making `Variant` and `Step` the primary key, with `SimTime` as a regular column.
+///
+/// The step is the record number of the print interval, so rows of different variants
+/// match exactly even if their simulation times differ by rounding errors.
+pub fn step_col_defs() -> Vec<ColDef> {
+    vec![
+        // not null is implied by the primary key, and would prevent adding it to existing tables
+        ColDef::new(
+            STEP_COL,
+            ColDataType::Number { decimal: false },
+            false,
+            true,
+        ),
+        ColDef::new(
+            MetaCol::SimulationTime.as_str(),
+            ColDataType::Number { decimal: true },
+            true,
+            false,
+        ),
+    ]
+}
+
+/// The columns of a table: the meta columns in front, followed by `cols`.
+/// Meta columns given in `cols` keep their definition, e.g. a `SimTime` outside the primary key.
+pub fn table_col_defs(cols: Vec<ColDef>) -> IndexSet<ColDef> {
+    let mut col_set: IndexSet<ColDef> = cols.into_iter().collect();
+    for (i, meta_col) in MetaCol::iter().enumerate() {
+        col_set.insert_before(i, meta_col.col_def());
+    }
+    col_set
+}
+
+/// The names of the primary key columns of the table with the columns `cols`.
+pub fn key_col_names(cols: &[ColDef]) -> Vec<String> {
+    table_col_defs(cols.to_vec())
+        .into_iter()
+        .filter(|col| col.primary_key)
+        .map(|col| col.name)
+        .collect()
+}
+
+/// Whether the column is a meta column, which every table of a unit holds.
+pub fn is_meta_col(name: &str) -> bool {
+    MetaCol::iter().any(|meta_col| meta_col.as_str() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_col_names() {
+        assert_eq!(key_col_names(&[]), vec!["Variant", "SimTime"]);
+        let mut cols = step_col_defs();
+        cols.push(ColDef::new(
+            "A",
+            ColDataType::Number { decimal: true },
+            false,
+            false,
+        ));
+        assert_eq!(key_col_names(&cols), vec!["Variant", "Step"]);
+        let names = table_col_defs(cols)
+            .into_iter()
+            .map(|col| col.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Variant", "SimTime", "Step", "A"]);
+    }
+}
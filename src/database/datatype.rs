@@ -5,10 +5,31 @@ use std::hash::{Hash, Hasher};
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum ColDataType {
     Text,
-    Number { decimal: bool },
+    /// Bytes of any length, e.g. the content of a file
+    Binary,
+    Number {
+        decimal: bool,
+    },
     DateTime,
 }
 
+impl ColDataType {
+    /// Maps an ODBC SQL type code, as reported in the `DATA_TYPE` column of the catalog.
+    pub fn from_sql_type(sql_type: i16) -> Self {
+        match sql_type {
+            // SQL_INTEGER, SQL_SMALLINT, SQL_BIGINT, SQL_TINYINT, SQL_BIT
+            4 | 5 | -5 | -6 | -7 => ColDataType::Number { decimal: false },
+            // SQL_NUMERIC, SQL_DECIMAL, SQL_FLOAT, SQL_REAL, SQL_DOUBLE
+            2 | 3 | 6 | 7 | 8 => ColDataType::Number { decimal: true },
+            // SQL_DATETIME, SQL_TYPE_DATE, SQL_TYPE_TIMESTAMP
+            9 | 91 | 93 => ColDataType::DateTime,
+            // SQL_BINARY, SQL_VARBINARY, SQL_LONGVARBINARY
+            -4..=-2 => ColDataType::Binary,
+            _ => ColDataType::Text,
+        }
+    }
+}
+
 #[derive(Eq, Debug, Clone)]
 pub struct ColDef {
     pub name: String,
@@ -42,6 +63,7 @@ impl ColDef {
     pub fn to_buffer_desc(&self) -> BufferDesc {
         match &self.data_type {
             ColDataType::Text => BufferDesc::Text { max_str_len: 255 },
+            ColDataType::Binary => BufferDesc::Binary { length: 65535 },
             ColDataType::Number { decimal } => BufferDesc::F64 { nullable: true },
             ColDataType::DateTime => BufferDesc::Date { nullable: true },
         }
@@ -1,10 +1,228 @@
 use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::ms_access::MsAccessProvider;
+use crate::database::ms_excel::MsExcelProvider;
+use crate::database::mysql::MySqlProvider;
+use crate::database::odbc::{OdbcProvider, OdbcProviderImpl};
+use crate::database::postgres::PostgreSQLProvider;
+use crate::database::registry::SharedConnection;
+use crate::database::sqlite::SqliteProvider;
+use crate::database::standard::StandardSqlProvider;
+use crate::trnsys::error::TrnSysError;
+use std::sync::Arc;
+use strum_macros::{Display, EnumString};
+use tracing::info;
+
+/// Maps (lowercase) parts of the DBMS name reported by the driver to the dialect to use.
+/// The first match wins.
+const DBMS_DIALECTS: [(&str, SqlDialectKind); 7] = [
+    ("access", SqlDialectKind::MsAccess),
+    ("excel", SqlDialectKind::MsExcel),
+    ("sqlite", SqlDialectKind::Sqlite),
+    ("postgresql", SqlDialectKind::PostgreSQL),
+    ("mysql", SqlDialectKind::MySql),
+    ("mariadb", SqlDialectKind::MySql),
+    ("microsoft sql server", SqlDialectKind::Odbc),
+];
+
+/// The SQL dialects known to the recorder, selectable by name, e.g. `Dialect=PostgreSQL`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum SqlDialectKind {
+    /// Detect the dialect from the DBMS name reported by the driver.
+    #[default]
+    Auto,
+    /// Bracket-quoted identifiers, as used by MS Access and SQL Server.
+    #[strum(serialize = "ODBC", serialize = "SqlServer")]
+    Odbc,
+    /// Standard SQL with double-quoted identifiers.
+    #[strum(serialize = "Standard")]
+    Standard,
+    #[strum(serialize = "Access", serialize = "MsAccess")]
+    MsAccess,
+    #[strum(serialize = "Excel", serialize = "MsExcel")]
+    MsExcel,
+    #[strum(serialize = "SQLite")]
+    Sqlite,
+    #[strum(serialize = "PostgreSQL", serialize = "Postgres")]
+    PostgreSQL,
+    #[strum(serialize = "MySQL", serialize = "MariaDB")]
+    MySql,
+}
+
+impl SqlDialectKind {
+    /// Picks the dialect matching the DBMS name, falling back to standard SQL.
+    pub fn from_dbms_name(dbms_name: &str) -> Self {
+        let dbms_name = dbms_name.to_lowercase();
+        DBMS_DIALECTS
+            .iter()
+            .find(|(pattern, _)| dbms_name.contains(pattern))
+            .map(|(_, kind)| *kind)
+            .unwrap_or(SqlDialectKind::Standard)
+    }
+
+    /// Creates a provider speaking this dialect on the given connection.
+    /// With [`SqlDialectKind::Auto`], the dialect is detected from the connected DBMS.
+    pub fn connect(
+        &self,
+        connection: Arc<SharedConnection<'static>>,
+    ) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> {
+        let kind = match self {
+            SqlDialectKind::Auto => {
+                let dbms_name = connection.lock()?.database_management_system_name()?;
+                let kind = SqlDialectKind::from_dbms_name(&dbms_name);
+                info!("Detected DBMS: {}, using dialect {}", dbms_name, kind);
+                kind
+            }
+            kind => *kind,
+        };
+        let mut provider = kind.new_provider();
+        provider.set_connection(connection)?;
+        Ok(provider)
+    }
+
+    /// Creates a provider speaking this dialect, without a connection.
+    fn new_provider(&self) -> Box<dyn OdbcProvider<'static>> {
+        match self {
+            SqlDialectKind::Auto | SqlDialectKind::Standard => Box::new(StandardSqlProvider::new()),
+            SqlDialectKind::Odbc => Box::new(OdbcProviderImpl::new()),
+            SqlDialectKind::MsAccess => Box::new(MsAccessProvider::new()),
+            SqlDialectKind::MsExcel => Box::new(MsExcelProvider::new()),
+            SqlDialectKind::Sqlite => Box::new(SqliteProvider::new()),
+            SqlDialectKind::PostgreSQL => Box::new(PostgreSQLProvider::new()),
+            SqlDialectKind::MySql => Box::new(MySqlProvider::new()),
+        }
+    }
+}
+
+/// How rows are written, selectable by name, e.g. `InsertMode=Upsert`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum InsertMode {
+    /// Fails on rows whose primary key exists.
+    #[default]
+    Insert,
+    /// Replaces rows whose primary key exists, so writing the same rows again is harmless.
+    Upsert,
+}
+
+/// The placeholders of `num_rows` rows of `num_cols` values, `(?, ?), (?, ?)`.
+pub(crate) fn values_placeholders(num_cols: usize, num_rows: usize) -> String {
+    let row = format!("({})", vec!["?"; num_cols].join(", "));
+    vec![row; num_rows].join(", ")
+}
+
+/// The names as a list of text literals, `'a', 'b'`, e.g. for `IN` in catalog queries.
+pub(crate) fn text_list<D: SqlDialect + ?Sized>(dialect: &D, names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| dialect.format_text_literal(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `INSERT ... ON CONFLICT (keys) DO UPDATE`, as supported by PostgreSQL and SQLite.
+pub(crate) fn on_conflict_upsert_query<D: SqlDialect + ?Sized>(
+    dialect: &D,
+    table_name: &str,
+    col_names: &[String],
+    key_cols: &[String],
+    num_rows: usize,
+) -> String {
+    let updates = col_names
+        .iter()
+        .filter(|name| !key_cols.contains(name))
+        .map(|name| {
+            let name = dialect.format_identifier(name);
+            format!("{} = excluded.{}", name, name)
+        })
+        .collect::<Vec<_>>();
+    let action = if updates.is_empty() {
+        "NOTHING".to_string()
+    } else {
+        format!("UPDATE SET {}", updates.join(", "))
+    };
+    format!(
+        "{} ON CONFLICT ({}) DO {}",
+        dialect.get_insert_query(table_name, col_names, num_rows),
+        key_cols
+            .iter()
+            .map(|name| dialect.format_identifier(name))
+            .collect::<Vec<_>>()
+            .join(", "),
+        action
+    )
+}
+
+/// What a driver can do. The providers fall back to alternative strategies for missing features.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderCapabilities {
+    /// `DELETE FROM` is supported, otherwise tables are rebuilt without the deleted rows.
+    pub delete: bool,
+    /// `ALTER TABLE ... ADD COLUMN` is supported, otherwise tables are rebuilt with the new columns.
+    pub alter_add_column: bool,
+    /// Batches are inserted in one transaction.
+    pub transactions: bool,
+    /// `CREATE` and `DROP TABLE` can be rolled back, so table rebuilds run in one transaction.
+    pub transactional_ddl: bool,
+    /// Batches are inserted with ODBC parameter arrays, otherwise row by row.
+    pub bulk_params: bool,
+    /// Maximum number of columns per table
+    pub max_columns: Option<usize>,
+    /// Maximum length of table and column names
+    pub max_identifier_length: Option<usize>,
+    /// Maximum number of rows per table, further rows roll over to continuation tables
+    pub max_rows: Option<u64>,
+    /// Maximum size of a database file in bytes, further rows roll over to a continuation file
+    pub max_file_size: Option<u64>,
+    /// Maximum number of parameters per statement, limiting the rows of multi-row inserts
+    pub max_params: Option<usize>,
+}
+
+impl Default for ProviderCapabilities {
+    fn default() -> Self {
+        ProviderCapabilities {
+            delete: true,
+            alter_add_column: true,
+            transactions: true,
+            transactional_ddl: false,
+            bulk_params: true,
+            max_columns: None,
+            max_identifier_length: None,
+            max_rows: None,
+            max_file_size: None,
+            max_params: None,
+        }
+    }
+}
+
+impl ProviderCapabilities {
+    pub fn check_num_columns(
+        &self,
+        table_name: &str,
+        num_columns: usize,
+    ) -> Result<(), TrnSysError> {
+        match self.max_columns {
+            Some(max_columns) if num_columns > max_columns => {
+                Err(TrnSysError::GeneralError(format!(
+                    "Table {} would have {} columns, but at most {} are supported.",
+                    table_name, num_columns, max_columns
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
 
 pub trait SqlDialect {
     fn get_text_type(&self) -> String {
         "TEXT".to_string()
     }
 
+    /// The type of binary columns without length limit.
+    fn get_binary_type(&self) -> String {
+        "BLOB".to_string()
+    }
+
     fn get_integer_type(&self) -> String {
         "INTEGER".to_string()
     }
@@ -25,9 +243,97 @@ pub trait SqlDialect {
         true
     }
 
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+
+    /// The statement renaming a table, if the dialect has one.
+    fn get_rename_table_query(&self, _from: &str, _to: &str) -> Option<String> {
+        None
+    }
+
+    /// The statement freeing the space of deleted rows, if the database needs one.
+    fn get_compact_query(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the driver can create secondary indexes.
+    fn support_index(&self) -> bool {
+        true
+    }
+
+    /// Whether `CREATE INDEX` accepts `IF NOT EXISTS`.
+    fn support_index_if_not_exists(&self) -> bool {
+        false
+    }
+
+    /// The name of the index on one column of a table.
+    fn get_index_name(&self, table_name: &str, col_name: &str) -> String {
+        format!("IX_{}_{}", table_name, col_name)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    fn get_create_index_query(&self, table_name: &str, index_name: &str, col_name: &str) -> String {
+        format!(
+            "CREATE INDEX {}{} ON {} ({})",
+            if self.support_index_if_not_exists() {
+                "IF NOT EXISTS "
+            } else {
+                ""
+            },
+            self.format_identifier(index_name),
+            table_name,
+            self.format_identifier(col_name)
+        )
+    }
+
+    /// Creates a unique index on the columns, with the syntax of [`Self::get_create_index_query`].
+    fn get_create_unique_index_query(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        col_names: &[String],
+    ) -> String {
+        format!(
+            "CREATE UNIQUE INDEX {}{} ON {} ({})",
+            if self.support_index_if_not_exists() {
+                "IF NOT EXISTS "
+            } else {
+                ""
+            },
+            self.format_identifier(index_name),
+            table_name,
+            col_names
+                .iter()
+                .map(|name| self.format_identifier(name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    /// A query counting the unique indexes, including the primary key, of the table
+    /// on exactly the key columns, for dialects whose upsert statement relies on one.
+    /// `None` if the upsert statement does not need a unique key.
+    fn get_unique_key_exists_query(
+        &self,
+        _table_name: &str,
+        _key_cols: &[String],
+    ) -> Option<String> {
+        None
+    }
+
+    /// A query counting the indexes of the table with the name, for dialects without
+    /// `IF NOT EXISTS`. Without one, an error about an existing index is ignored.
+    fn get_index_exists_query(&self, _table_name: &str, _index_name: &str) -> Option<String> {
+        None
+    }
+
     fn get_col_def_str(&self, col_def: &ColDef) -> String {
         let mut data_type_str = match col_def.data_type {
             ColDataType::Text => self.get_text_type(),
+            ColDataType::Binary => self.get_binary_type(),
             ColDataType::Number { decimal } => {
                 if decimal {
                     self.get_decimal_type()
@@ -52,6 +358,41 @@ pub trait SqlDialect {
         format!("[{}]", identifier.replace("]", "]]"))
     }
 
+    fn format_text_literal(&self, text: &str) -> String {
+        format!("'{}'", text.replace('\'', "''"))
+    }
+
+    /// Whether `INSERT ... VALUES (...), (...)` inserts several rows at once.
+    fn support_multi_row_values(&self) -> bool {
+        true
+    }
+
+    /// `INSERT` of `num_rows` rows with a placeholder per column.
+    fn get_insert_query(&self, table_name: &str, col_names: &[String], num_rows: usize) -> String {
+        format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            table_name,
+            col_names
+                .iter()
+                .map(|name| self.format_identifier(name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            values_placeholders(col_names.len(), num_rows)
+        )
+    }
+
+    /// The statement inserting `num_rows` rows, or updating the rows with the same `key_cols`,
+    /// with a placeholder per column. Without one, existing rows are deleted before inserting.
+    fn get_upsert_query(
+        &self,
+        _table_name: &str,
+        _col_names: &[String],
+        _key_cols: &[String],
+        _num_rows: usize,
+    ) -> Option<String> {
+        None
+    }
+
     fn get_primary_key_str(&self, col_defs: Vec<&ColDef>) -> String {
         if !self.support_primary_key() {
             return "".to_string();
@@ -73,3 +414,72 @@ pub trait SqlDialect {
         format!("PRIMARY KEY ({})", primary_key_str)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_dialect_from_dbms_name() {
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("PostgreSQL"),
+            SqlDialectKind::PostgreSQL
+        );
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("ACCESS"),
+            SqlDialectKind::MsAccess
+        );
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("SQLite"),
+            SqlDialectKind::Sqlite
+        );
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("Microsoft SQL Server"),
+            SqlDialectKind::Odbc
+        );
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("MySQL"),
+            SqlDialectKind::MySql
+        );
+        assert_eq!(
+            SqlDialectKind::from_dbms_name("Some Unknown DB"),
+            SqlDialectKind::Standard
+        );
+    }
+
+    #[test]
+    fn test_on_conflict_upsert_query() {
+        let provider = SqliteProvider::new();
+        let col_names = ["Variant", "SimTime", "A"].map(String::from);
+        let key_cols = ["Variant", "SimTime"].map(String::from);
+        assert_eq!(
+            on_conflict_upsert_query(&provider, "Result", &col_names, &key_cols, 1),
+            "INSERT INTO Result ([Variant], [SimTime], [A]) VALUES (?, ?, ?) \
+            ON CONFLICT ([Variant], [SimTime]) DO UPDATE SET [A] = excluded.[A]"
+        );
+        assert!(
+            on_conflict_upsert_query(&provider, "Result", &key_cols, &key_cols, 2)
+                .ends_with("DO NOTHING")
+        );
+    }
+
+    #[test]
+    fn test_values_placeholders() {
+        assert_eq!(values_placeholders(2, 1), "(?, ?)");
+        assert_eq!(values_placeholders(1, 3), "(?), (?), (?)");
+    }
+
+    #[test]
+    fn test_dialect_from_str() {
+        assert_eq!(
+            SqlDialectKind::from_str("postgres").unwrap(),
+            SqlDialectKind::PostgreSQL
+        );
+        assert_eq!(
+            SqlDialectKind::from_str("auto").unwrap(),
+            SqlDialectKind::Auto
+        );
+        assert!(SqlDialectKind::from_str("oracle").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::database::column::MetaCol;
+use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::odbc::OdbcProvider;
+use crate::database::path::clean_and_ensure_path;
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use indexmap::IndexSet;
+use std::fs;
+use std::path::Path;
+
+/// Metadata table recording in a full file where the rows of each variant continue.
+pub const FILE_TABLE: &str = "RecorderFiles";
+
+/// Connects to the database file at the given path.
+pub type ConnectFile =
+    Box<dyn Fn(&str) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> + Send + Sync>;
+
+/// Moves on to the continuation files `result_2.db`, `result_3.db`, ...
+/// once the database file exceeds `max_file_size` bytes.
+pub struct FileRollover {
+    base_path: String,
+    max_file_size: u64,
+    file_no: u32,
+    connect: ConnectFile,
+}
+
+impl FileRollover {
+    pub fn new(
+        db_path: &str,
+        max_file_size: u64,
+        connect: ConnectFile,
+    ) -> Result<Self, TrnSysError> {
+        Ok(FileRollover {
+            base_path: clean_and_ensure_path(db_path)?,
+            max_file_size,
+            file_no: 1,
+            connect,
+        })
+    }
+
+    /// The path of the n-th file, counting from 1 for the base file itself.
+    pub fn file_path(base_path: &str, file_no: u32) -> String {
+        if file_no <= 1 {
+            return base_path.to_string();
+        }
+        let path = Path::new(base_path);
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy())
+            .unwrap_or_default();
+        let file_name = match path.extension() {
+            Some(ext) => format!("{}_{}.{}", stem, file_no, ext.to_string_lossy()),
+            None => format!("{}_{}", stem, file_no),
+        };
+        path.with_file_name(file_name).to_string_lossy().to_string()
+    }
+
+    pub fn current_path(&self) -> String {
+        Self::file_path(&self.base_path, self.file_no)
+    }
+
+    pub fn next_path(&self) -> String {
+        Self::file_path(&self.base_path, self.file_no + 1)
+    }
+
+    /// The existing continuation files, in order.
+    pub fn continuation_paths(&self) -> Result<Vec<String>, TrnSysError> {
+        let mut paths = vec![];
+        let mut file_no = 2;
+        loop {
+            let path = Self::file_path(&self.base_path, file_no);
+            if !fs::exists(&path)? {
+                return Ok(paths);
+            }
+            paths.push(path);
+            file_no += 1;
+        }
+    }
+
+    /// Whether the current file reached the size limit, including its SQLite write-ahead log,
+    /// which holds the latest rows until they are checkpointed into the file.
+    pub fn is_full(&self) -> Result<bool, TrnSysError> {
+        let path = self.current_path();
+        let size = [path.clone(), format!("{}-wal", path)]
+            .iter()
+            .map(|path| match fs::exists(path)? {
+                true => Ok(fs::metadata(path)?.len()),
+                false => Ok(0),
+            })
+            .sum::<Result<u64, TrnSysError>>()?;
+        Ok(size >= self.max_file_size)
+    }
+
+    pub fn connect(&self, path: &str) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> {
+        (self.connect)(path)
+    }
+
+    /// Connects to the next file, creating it if needed.
+    pub fn advance(&mut self) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> {
+        self.file_no += 1;
+        self.connect(&self.current_path())
+    }
+}
+
+/// Records in the full file that the rows of the variant continue in `next_path`.
+pub fn record_split(
+    provider: &dyn OdbcProvider<'_>,
+    variant_name: &str,
+    next_path: &str,
+    first_sim_time: Option<f64>,
+) -> Result<(), TrnSysError> {
+    provider.ensure_plain_table(FILE_TABLE, file_col_defs(), None)?;
+    let next_file = Path::new(next_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    provider.insert_data(
+        FILE_TABLE,
+        vec![
+            (MetaCol::Variant.as_str().to_string(), variant_name.into()),
+            ("NextFile".to_string(), next_file.into()),
+            (
+                "FirstSimTime".to_string(),
+                first_sim_time.map(DbValue::Number).unwrap_or(DbValue::Null),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.to_parameter()))
+        .collect(),
+    )
+}
+
+/// Removes the splits of the variant recorded in the file.
+pub fn remove_splits(
+    provider: &dyn OdbcProvider<'_>,
+    variant_name: &str,
+) -> Result<(), TrnSysError> {
+    if provider.has_table(FILE_TABLE)? {
+        provider.delete_rows(FILE_TABLE, provider.variant_condition(variant_name))?;
+    }
+    Ok(())
+}
+
+/// The columns of the file table.
+fn file_col_defs() -> IndexSet<ColDef> {
+    IndexSet::from([
+        MetaCol::Variant.col_def(),
+        ColDef::new("NextFile", ColDataType::Text, true, false),
+        ColDef::new(
+            "FirstSimTime",
+            ColDataType::Number { decimal: true },
+            false,
+            false,
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path() {
+        let base = Path::new("results").join("run.accdb");
+        let base = base.to_str().unwrap();
+        assert_eq!(FileRollover::file_path(base, 1), base);
+        assert_eq!(
+            FileRollover::file_path(base, 2),
+            Path::new("results").join("run_2.accdb").to_str().unwrap()
+        );
+        assert_eq!(FileRollover::file_path("run", 3), "run_3");
+    }
+
+    #[test]
+    fn test_is_full_with_wal() {
+        let path = std::env::temp_dir().join("test_rollover_wal.db");
+        let path = path.to_str().unwrap();
+        let wal_path = format!("{}-wal", path);
+        let rollover = FileRollover::new(path, 15, Box::new(|_| unreachable!())).unwrap();
+        assert!(!rollover.is_full().unwrap());
+        fs::write(path, [0; 10]).unwrap();
+        assert!(!rollover.is_full().unwrap());
+        fs::write(&wal_path, [0; 10]).unwrap();
+        assert!(rollover.is_full().unwrap());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&wal_path).unwrap();
+    }
+}
@@ -1,50 +1,79 @@
 use crate::trnsys::error::*;
 
+pub mod artifacts;
 pub(super) mod column;
 pub mod ms_access;
 pub mod ms_excel;
+pub mod mysql;
 pub mod odbc;
 pub mod postgres;
+pub mod registry;
+pub mod retry;
+pub mod rollover;
+pub mod secret;
+pub mod shard;
 pub mod sqlite;
+pub mod standard;
 pub mod template;
+pub mod writer;
+pub mod xlsx;
 
 pub(super) mod datatype;
+pub mod value;
 
 pub(super) mod cursor;
 
 pub mod buffer;
-mod dialect;
+pub mod dialect;
+pub mod file_rollover;
 mod path;
 
 #[macro_export]
 macro_rules! impl_odbc_provider {
-    ($provider:ident) => {
+    ($provider:ident $(, $field:ident: $default:expr)*) => {
         impl $provider<'_> {
             pub fn new() -> Self {
-                $provider { connection: None }
+                $provider {
+                    connection: None,
+                    capabilities: None,
+                    $($field: $default,)*
+                }
             }
         }
 
         impl<'c> OdbcProvider<'c> for $provider<'c> {
-            fn set_connection(&mut self, connection: Connection<'c>) -> Result<(), TrnSysError> {
+            fn set_connection(
+                &mut self,
+                connection: std::sync::Arc<SharedConnection<'c>>,
+            ) -> Result<(), TrnSysError> {
                 if self.connection.is_some() {
                     return Err(TrnSysError::GeneralError(
                         "Connection already set, cannot be set again.".to_string(),
                     ));
                 }
-                self.connection = Some(Mutex::new(connection));
+                self.connection = Some(connection);
                 Ok(())
             }
 
-            fn get_connection(&self) -> Result<std::sync::MutexGuard<Connection<'c>>, TrnSysError> {
-                Ok(self
-                    .connection
+            fn get_capabilities(&self) -> ProviderCapabilities {
+                self.capabilities
+                    .clone()
+                    .unwrap_or_else(|| self.default_capabilities())
+            }
+
+            fn set_capabilities(&mut self, capabilities: ProviderCapabilities) {
+                self.capabilities = Some(capabilities);
+            }
+
+            fn get_connection(
+                &self,
+            ) -> Result<std::sync::MutexGuard<'_, Connection<'c>>, TrnSysError> {
+                self.connection
                     .as_ref()
                     .ok_or(TrnSysError::GeneralError(
                         "DB Connection not set.".to_string(),
                     ))?
                     .lock()
-                    .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))?)
             }
         }
     };
@@ -52,12 +81,20 @@ macro_rules! impl_odbc_provider {
 
 #[cfg(test)]
 mod tests {
+    use crate::database::artifacts::{store_artifacts, Artifact, ARTIFACT_TABLE};
+    use crate::database::column::{key_col_names, step_col_defs};
     use crate::database::datatype::{ColDataType, ColDef};
+    use crate::database::dialect::{InsertMode, ProviderCapabilities};
     use crate::database::ms_access::MsAccessProvider;
     use crate::database::ms_excel::MsExcelProvider;
-    use crate::database::odbc::FileDbProvider;
+    use crate::database::odbc::{FileDbProvider, OdbcProvider};
     use crate::database::path::clean_and_ensure_path;
+    use crate::database::registry::ConnectionRegistry;
+    use crate::database::rollover::{TableRollover, SEGMENT_TABLE};
+    use crate::database::shard::SHARD_TABLE;
     use crate::database::sqlite::SqliteProvider;
+    use crate::database::value::DbValue;
+    use crate::database::writer::{OdbcWriter, RecordWriter};
     use odbc_api::{Environment, IntoParameter};
     use std::fs;
     use std::sync::LazyLock;
@@ -68,6 +105,50 @@ mod tests {
         Environment::new().unwrap()
     });
 
+    static CONNECTIONS: LazyLock<ConnectionRegistry> =
+        LazyLock::new(|| ConnectionRegistry::new(&ENVIRONMENT));
+
+    /// Runs `test` with the path of a database file, removing the file before and after.
+    fn with_db_file(file_name: &str, test: impl FnOnce(&str)) {
+        let path = clean_and_ensure_path(file_name).unwrap();
+        let remove = || {
+            if fs::metadata(&path).is_ok() {
+                fs::remove_file(&path).unwrap();
+            }
+        };
+        remove();
+        test(&path);
+        remove();
+    }
+
+    /// A SQLite provider connected to the file.
+    fn sqlite_provider(db_path: &str) -> SqliteProvider<'static> {
+        let mut provider = SqliteProvider::new();
+        provider.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
+        provider
+    }
+
+    /// The number column `A` the tables of the tests hold besides the meta columns.
+    fn col_a() -> ColDef {
+        ColDef::new("A", ColDataType::Number { decimal: true }, false, false)
+    }
+
+    /// The names of the meta columns and `A`.
+    fn col_names() -> Vec<String> {
+        vec![
+            "Variant".to_string(),
+            "SimTime".to_string(),
+            "A".to_string(),
+        ]
+    }
+
+    /// `n` rows of the variant at the simulation times 0, 1, ..., with `a` as the value of `A`.
+    fn rows(variant: &str, n: usize, a: f64) -> Vec<Vec<DbValue>> {
+        (0..n)
+            .map(|i| vec![variant.into(), (i as f64).into(), a.into()])
+            .collect()
+    }
+
     fn test_db(provider: &mut dyn FileDbProvider) {
         // ensure table
         let cols = vec![
@@ -139,7 +220,7 @@ mod tests {
 
         {
             let mut provider = MsAccessProvider::new();
-            provider.setup_by_path(&ENVIRONMENT, db_path, None).unwrap();
+            provider.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
 
             test_db(&mut provider);
         }
@@ -160,7 +241,7 @@ mod tests {
 
         {
             let mut provider = MsExcelProvider::new();
-            provider.setup_by_path(&ENVIRONMENT, db_path, None).unwrap();
+            provider.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
 
             test_db(&mut provider);
         }
@@ -180,7 +261,7 @@ mod tests {
 
         {
             let mut provider = SqliteProvider::new();
-            provider.setup_by_path(&ENVIRONMENT, db_path, None).unwrap();
+            provider.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
 
             test_db(&mut provider);
         }
@@ -188,4 +269,356 @@ mod tests {
             fs::remove_file(db_path).unwrap();
         }
     }
+
+    #[traced_test]
+    #[test]
+    fn test_shared_connection() {
+        with_db_file("test_shared.db", |db_path| {
+            let mut first = SqliteProvider::new();
+            first.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
+            let mut second = SqliteProvider::new();
+            second.setup_by_path(&CONNECTIONS, db_path, None).unwrap();
+
+            let conn_str = format!("DBQ={};Driver={{{}}}", db_path, first.get_driver_name());
+            let a = CONNECTIONS.connect_by_conn_str(&conn_str, None).unwrap();
+            let b = CONNECTIONS.connect_by_conn_str(&conn_str, None).unwrap();
+            assert!(std::sync::Arc::ptr_eq(&a, &b));
+
+            test_db(&mut first);
+            second
+                .ensure_table("TestTable", vec![], None)
+                .expect("Error Ensuring Table");
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_limited_capabilities() {
+        with_db_file("test_limited.db", |db_path| {
+            let col_b = ColDef::new("B", ColDataType::Number { decimal: true }, false, false);
+            let mut provider = sqlite_provider(db_path);
+            provider
+                .ensure_table("Limited", vec![col_a()], None)
+                .unwrap();
+            // parameter arrays and transactions
+            provider
+                .batch_insert_data("Limited", col_names(), rows("V1", 2, 1.5))
+                .unwrap();
+
+            provider.set_capabilities(ProviderCapabilities {
+                delete: false,
+                alter_add_column: false,
+                transactions: false,
+                transactional_ddl: false,
+                bulk_params: false,
+                max_columns: Some(4),
+                max_identifier_length: Some(8),
+                max_rows: None,
+                max_file_size: None,
+                // a row per statement
+                max_params: Some(3),
+            });
+            provider
+                .batch_insert_data("Limited", col_names(), rows("V2", 2, 1.5))
+                .unwrap();
+
+            // add a column without ALTER TABLE
+            provider
+                .ensure_table("Limited", vec![col_a(), col_b.clone()], None)
+                .unwrap();
+            assert_eq!(provider.count_rows("Limited", None).unwrap(), 4);
+
+            // remove a variant without DELETE
+            provider.remove_variant("Limited", "V1").unwrap();
+            assert_eq!(provider.count_rows("Limited", None).unwrap(), 2);
+
+            // limits
+            let col_c = ColDef::new("C", ColDataType::Number { decimal: true }, false, false);
+            assert!(provider
+                .ensure_table("Limited", vec![col_a(), col_b, col_c], None)
+                .is_err());
+            assert!(provider
+                .ensure_table("TooLongName", vec![col_a()], None)
+                .is_err());
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_interrupted_rebuild() {
+        with_db_file("test_rebuild.db", |db_path| {
+            let mut provider = sqlite_provider(db_path);
+            provider.set_capabilities(ProviderCapabilities {
+                delete: false,
+                ..ProviderCapabilities::default()
+            });
+            provider
+                .ensure_table("Rebuilt", vec![col_a()], None)
+                .unwrap();
+            let rows = [rows("V0", 2, 1.5), rows("V1", 2, 1.5)].concat();
+            provider
+                .batch_insert_data("Rebuilt", col_names(), rows)
+                .unwrap();
+
+            // interrupted after dropping the table, only the rebuilt rows are left
+            provider
+                .get_connection()
+                .unwrap()
+                .execute("ALTER TABLE Rebuilt RENAME TO Rebuilt_rebuild", ())
+                .unwrap();
+            provider.remove_variant("Rebuilt", "V0").unwrap();
+            assert_eq!(provider.count_rows("Rebuilt", None).unwrap(), 2);
+            {
+                let connection = provider.get_connection().unwrap();
+                assert!(!provider
+                    .table_exists(&connection, "Rebuilt_rebuild")
+                    .unwrap());
+                // interrupted before dropping the table, unclear which one is complete
+                connection
+                    .execute("CREATE TABLE Rebuilt_rebuild AS SELECT * FROM Rebuilt", ())
+                    .unwrap();
+            }
+            // no rows to remove, so the table is not rebuilt
+            provider.remove_variant("Rebuilt", "V0").unwrap();
+            assert!(provider.remove_variant("Rebuilt", "V1").is_err());
+            assert_eq!(provider.count_rows("Rebuilt", None).unwrap(), 2);
+            assert_eq!(provider.count_rows("Rebuilt_rebuild", None).unwrap(), 2);
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_rollover() {
+        with_db_file("test_rollover.db", |db_path| {
+            let provider = sqlite_provider(db_path);
+            let mut rollover = TableRollover::new("Rolled", "V1", vec![col_a()], Some(2));
+            rollover.start(&provider).unwrap();
+            rollover
+                .write(&provider, col_names(), rows("V1", 5, 1.5))
+                .unwrap();
+            assert_eq!(provider.count_rows("Rolled", None).unwrap(), 2);
+            assert_eq!(provider.count_rows("Rolled_2", None).unwrap(), 2);
+            assert_eq!(provider.count_rows("Rolled_3", None).unwrap(), 1);
+            assert_eq!(provider.count_rows(SEGMENT_TABLE, None).unwrap(), 3);
+
+            // a rerun of the variant replaces all of its rows
+            let mut rollover = TableRollover::new("Rolled", "V1", vec![col_a()], Some(2));
+            rollover.start(&provider).unwrap();
+            assert_eq!(provider.count_rows("Rolled_2", None).unwrap(), 0);
+            assert_eq!(provider.count_rows(SEGMENT_TABLE, None).unwrap(), 0);
+            rollover
+                .write(&provider, col_names(), rows("V1", 5, 1.5))
+                .unwrap();
+            assert_eq!(provider.count_rows("Rolled_3", None).unwrap(), 1);
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_indexes() {
+        with_db_file("test_indexes.db", |db_path| {
+            let indexes = vec!["Variant".to_string(), "A".to_string()];
+            let provider = sqlite_provider(db_path);
+            // the primary key starts with the variant, so only A gets an index
+            for _ in 0..2 {
+                TableRollover::new("Indexed", "V1", vec![col_a()], None)
+                    .with_indexes(indexes.clone())
+                    .start(&provider)
+                    .unwrap();
+            }
+            assert_eq!(
+                provider
+                    .count_rows(
+                        "sqlite_master",
+                        Some("type = 'index' AND name LIKE 'IX_Indexed_%'".to_string())
+                    )
+                    .unwrap(),
+                1
+            );
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_artifacts() {
+        with_db_file("test_artifacts.db", |db_path| {
+            let provider = sqlite_provider(db_path);
+            let artifacts = |content: &[u8]| {
+                vec![Artifact {
+                    kind: "Deck".to_string(),
+                    file_name: "Office.dck".to_string(),
+                    content: content.to_vec(),
+                }]
+            };
+            // Windows-1252 text, e.g. "* Raum 20 °C", is stored unchanged
+            let deck = b"* Raum 20 \xb0C\n".repeat(10_000);
+            store_artifacts(&provider, "V1", "2024-03-05T14:30:00Z", artifacts(&deck)).unwrap();
+            // another run of the same variant
+            store_artifacts(&provider, "V1", "2024-03-06T09:00:00Z", artifacts(b"")).unwrap();
+            assert_eq!(provider.count_rows(ARTIFACT_TABLE, None).unwrap(), 2);
+            assert_eq!(
+                provider
+                    .count_rows(
+                        ARTIFACT_TABLE,
+                        Some(format!("LENGTH([Content]) = {}", deck.len()))
+                    )
+                    .unwrap(),
+                1
+            );
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_upsert() {
+        with_db_file("test_upsert.db", |db_path| {
+            let keys = key_col_names(&[]);
+            let write = |provider: &SqliteProvider, a: f64, mode: InsertMode| {
+                provider.write_rows("Upserted", col_names(), rows("V1", 3, a), mode, &keys)
+            };
+
+            let mut provider = sqlite_provider(db_path);
+            provider
+                .ensure_table("Upserted", vec![col_a()], None)
+                .unwrap();
+            write(&provider, 1.0, InsertMode::Insert).unwrap();
+            assert!(write(&provider, 2.0, InsertMode::Insert).is_err());
+            write(&provider, 2.0, InsertMode::Upsert).unwrap();
+            assert_eq!(provider.count_rows("Upserted", None).unwrap(), 3);
+            assert_eq!(
+                provider
+                    .count_rows("Upserted", Some("[A] = 2".to_string()))
+                    .unwrap(),
+                3
+            );
+
+            // multi-row statements of two rows and one with the remaining row
+            provider.set_capabilities(ProviderCapabilities {
+                bulk_params: false,
+                max_params: Some(6),
+                ..ProviderCapabilities::default()
+            });
+            write(&provider, 3.0, InsertMode::Upsert).unwrap();
+            assert_eq!(provider.count_rows("Upserted", None).unwrap(), 3);
+            assert_eq!(
+                provider
+                    .count_rows("Upserted", Some("[A] = 3".to_string()))
+                    .unwrap(),
+                3
+            );
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_step_key() {
+        with_db_file("test_step_key.db", |db_path| {
+            let mut cols = step_col_defs();
+            cols.push(col_a());
+            let keys = key_col_names(&cols);
+            let col_names = vec![
+                "Variant".to_string(),
+                "SimTime".to_string(),
+                "Step".to_string(),
+                "A".to_string(),
+            ];
+            // the simulation times differ by rounding errors, the steps do not
+            let rows = |sim_time: f64, a: f64| {
+                (0..3)
+                    .map(|i| {
+                        vec![
+                            "V1".into(),
+                            (i as f64 * sim_time).into(),
+                            DbValue::Integer(i),
+                            a.into(),
+                        ]
+                    })
+                    .collect::<Vec<Vec<DbValue>>>()
+            };
+
+            let provider = sqlite_provider(db_path);
+            provider
+                .ensure_table("Stepped", cols.clone(), None)
+                .unwrap();
+            provider
+                .write_rows(
+                    "Stepped",
+                    col_names.clone(),
+                    rows(0.1, 1.0),
+                    InsertMode::Insert,
+                    &keys,
+                )
+                .unwrap();
+            provider
+                .write_rows(
+                    "Stepped",
+                    col_names.clone(),
+                    rows(0.1000001, 2.0),
+                    InsertMode::Upsert,
+                    &keys,
+                )
+                .unwrap();
+            assert_eq!(provider.count_rows("Stepped", None).unwrap(), 3);
+            assert_eq!(
+                provider
+                    .count_rows("Stepped", Some("[A] = 2".to_string()))
+                    .unwrap(),
+                3
+            );
+
+            // a table created with Variant and SimTime as the primary key gets a unique index
+            provider
+                .ensure_table("Legacy", vec![col_a()], None)
+                .unwrap();
+            provider.ensure_table("Legacy", cols, None).unwrap();
+            provider.ensure_upsert_key("Legacy", &keys).unwrap();
+            for (sim_time, a) in [(0.1, 1.0), (0.1000001, 2.0)] {
+                provider
+                    .write_rows(
+                        "Legacy",
+                        col_names.clone(),
+                        rows(sim_time, a),
+                        InsertMode::Upsert,
+                        &keys,
+                    )
+                    .unwrap();
+            }
+            assert_eq!(provider.count_rows("Legacy", None).unwrap(), 3);
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_sqlite_column_shards() {
+        with_db_file("test_shards.db", |db_path| {
+            let cols = ["A", "B", "C", "D", "E"]
+                .map(|name| ColDef::new(name, ColDataType::Number { decimal: true }, false, false))
+                .to_vec();
+            let col_names = ["Variant", "SimTime", "A", "B", "C", "D", "E"]
+                .map(|name| name.to_string())
+                .to_vec();
+            let rows = (0..3)
+                .map(|i| {
+                    let mut row: Vec<DbValue> = vec!["V1".into(), (i as f64).into()];
+                    row.extend((0..5).map(|c| DbValue::Number(c as f64)));
+                    row
+                })
+                .collect::<Vec<_>>();
+
+            let mut provider = sqlite_provider(db_path);
+            provider.set_capabilities(ProviderCapabilities {
+                max_columns: Some(4),
+                ..ProviderCapabilities::default()
+            });
+            let mut writer = OdbcWriter::new(Box::new(provider), "Wide", "V1", cols, None);
+            writer.start().unwrap();
+            writer.write(col_names, rows).unwrap();
+
+            let provider = sqlite_provider(db_path);
+            for table in ["Wide", "Wide_Cols2", "Wide_Cols3"] {
+                assert_eq!(provider.count_rows(table, None).unwrap(), 3);
+            }
+            assert_eq!(provider.count_rows(SHARD_TABLE, None).unwrap(), 5);
+        });
+    }
 }
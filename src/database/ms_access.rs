@@ -1,19 +1,42 @@
 use super::odbc::{FileDbProvider, OdbcProvider};
 use super::template::TemplateFile;
 use super::*;
-use crate::database::dialect::SqlDialect;
+use crate::database::dialect::{ProviderCapabilities, SqlDialect};
+use crate::database::registry::SharedConnection;
 use crate::impl_odbc_provider;
 use odbc_api::Connection;
-use std::sync::Mutex;
+use std::sync::Arc;
 
 pub struct MsAccessProvider<'c> {
-    connection: Option<Mutex<Connection<'c>>>,
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
 }
 
 impl SqlDialect for MsAccessProvider<'_> {
     fn get_decimal_type(&self) -> String {
         "FLOAT".to_string()
     }
+
+    // OLE Object in the Access designer
+    fn get_binary_type(&self) -> String {
+        "LONGBINARY".to_string()
+    }
+
+    // Access inserts a single row per INSERT ... VALUES
+    fn support_multi_row_values(&self) -> bool {
+        false
+    }
+
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            bulk_params: false,
+            max_columns: Some(255),
+            max_identifier_length: Some(64),
+            // Access files hard-fail at 2 GB, leave room for the next flush
+            max_file_size: Some(1900 * 1024 * 1024),
+            ..Default::default()
+        }
+    }
 }
 
 impl_odbc_provider!(MsAccessProvider);
@@ -1,15 +1,18 @@
 use super::odbc::{FileDbProvider, OdbcProvider};
 use super::template::TemplateFile;
 use super::*;
-use crate::database::dialect::SqlDialect;
+use crate::database::dialect::{ProviderCapabilities, SqlDialect};
 use crate::database::path::clean_and_ensure_path;
+use crate::database::registry::ConnectionRegistry;
+use crate::database::registry::SharedConnection;
 use crate::impl_odbc_provider;
-use odbc_api::{Connection, ConnectionOptions, Environment};
-use std::sync::Mutex;
+use odbc_api::{Connection, ConnectionOptions};
+use std::sync::Arc;
 use tracing::info;
 
 pub struct MsExcelProvider<'c> {
-    connection: Option<Mutex<Connection<'c>>>,
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
 }
 
 impl SqlDialect for MsExcelProvider<'_> {
@@ -27,6 +30,32 @@ impl SqlDialect for MsExcelProvider<'_> {
     fn support_primary_key(&self) -> bool {
         false
     }
+
+    // Sheets have no indexes
+    fn support_index(&self) -> bool {
+        false
+    }
+
+    fn support_multi_row_values(&self) -> bool {
+        false
+    }
+
+    // The Excel driver can neither delete rows nor add columns to a sheet
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            delete: false,
+            alter_add_column: false,
+            transactions: false,
+            transactional_ddl: false,
+            bulk_params: false,
+            max_columns: Some(255),
+            max_identifier_length: Some(64),
+            // 1,048,576 rows per sheet, including the header row
+            max_rows: Some(1_048_575),
+            max_file_size: None,
+            max_params: None,
+        }
+    }
 }
 
 impl_odbc_provider!(MsExcelProvider);
@@ -42,7 +71,7 @@ impl<'c> FileDbProvider<'c> for MsExcelProvider<'c> {
 
     fn setup_by_path(
         &mut self,
-        environment: &'c Environment,
+        registry: &ConnectionRegistry<'c>,
         db_path: &str,
         conn_options: Option<ConnectionOptions>,
     ) -> Result<(), TrnSysError>
@@ -52,14 +81,13 @@ impl<'c> FileDbProvider<'c> for MsExcelProvider<'c> {
         let db_path = clean_and_ensure_path(db_path)?;
         info!("DB Path: {}", db_path);
         self.ensure_file_exists(&db_path)?;
-        self.setup_by_conn_str(
-            environment,
+        self.set_connection(registry.connect_by_expanded_conn_str(
             &format!(
                 "DRIVER={{{}}};DBQ={};FIRSTROWHASNAMES=1;READONLY=FALSE;",
                 self.get_driver_name(),
                 db_path
             ),
             conn_options,
-        )
+        )?)
     }
 }
@@ -0,0 +1,91 @@
+use super::odbc::OdbcProvider;
+use super::*;
+use crate::database::dialect::{text_list, ProviderCapabilities, SqlDialect};
+use crate::database::registry::SharedConnection;
+use crate::impl_odbc_provider;
+use odbc_api::Connection;
+use std::sync::Arc;
+
+pub struct MySqlProvider<'c> {
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
+}
+
+impl SqlDialect for MySqlProvider<'_> {
+    fn get_rename_table_query(&self, from: &str, to: &str) -> Option<String> {
+        Some(format!("ALTER TABLE {} RENAME TO {}", from, to))
+    }
+
+    // MySQL cannot use TEXT columns in a primary key without a prefix length
+    fn get_text_type(&self) -> String {
+        "VARCHAR(255)".to_string()
+    }
+
+    // BLOB holds at most 64 KiB in MySQL
+    fn get_binary_type(&self) -> String {
+        "LONGBLOB".to_string()
+    }
+
+    // MySQL uses backticks for identifiers
+    fn format_identifier(&self, identifier: &str) -> String {
+        format!("`{}`", identifier.replace("`", "``"))
+    }
+
+    fn get_upsert_query(
+        &self,
+        table_name: &str,
+        col_names: &[String],
+        key_cols: &[String],
+        num_rows: usize,
+    ) -> Option<String> {
+        // updating a key column to its own value makes the statement valid without other columns
+        let updates = col_names
+            .iter()
+            .filter(|name| !key_cols.contains(name))
+            .chain(key_cols.first())
+            .map(|name| {
+                let name = self.format_identifier(name);
+                format!("{} = VALUES({})", name, name)
+            })
+            .collect::<Vec<_>>();
+        Some(format!(
+            "{} ON DUPLICATE KEY UPDATE {}",
+            self.get_insert_query(table_name, col_names, num_rows),
+            updates.join(", ")
+        ))
+    }
+
+    // MySQL has no CREATE INDEX IF NOT EXISTS
+    fn get_index_exists_query(&self, table_name: &str, index_name: &str) -> Option<String> {
+        Some(format!(
+            "SELECT COUNT(*) FROM information_schema.statistics \
+            WHERE table_schema = DATABASE() AND table_name = {} AND index_name = {}",
+            self.format_text_literal(table_name),
+            self.format_text_literal(index_name)
+        ))
+    }
+
+    // ON DUPLICATE KEY UPDATE replaces the row conflicting with any unique index
+    fn get_unique_key_exists_query(&self, table_name: &str, key_cols: &[String]) -> Option<String> {
+        Some(format!(
+            "SELECT COUNT(*) FROM (SELECT index_name FROM information_schema.statistics \
+            WHERE table_schema = DATABASE() AND table_name = {} AND non_unique = 0 \
+            GROUP BY index_name HAVING COUNT(*) = {} AND SUM(column_name IN ({})) = {}) AS k",
+            self.format_text_literal(table_name),
+            key_cols.len(),
+            text_list(self, key_cols),
+            key_cols.len()
+        ))
+    }
+
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            max_columns: Some(4096),
+            max_identifier_length: Some(64),
+            max_params: Some(65535),
+            ..Default::default()
+        }
+    }
+}
+
+impl_odbc_provider!(MySqlProvider);
@@ -1,74 +1,56 @@
-use super::column::MetaCol;
+use super::column::{table_col_defs, MetaCol};
 use super::cursor::CursorQuery;
 use super::template::TemplateFile;
 use crate::database::datatype::{ColDataType, ColDef, DataTypeQuery};
-use crate::database::dialect::SqlDialect;
+use crate::database::dialect::{values_placeholders, InsertMode, ProviderCapabilities, SqlDialect};
 use crate::database::path::clean_and_ensure_path;
+use crate::database::registry::{ConnectionRegistry, SharedConnection};
+use crate::database::retry::{is_already_exists, with_retry};
+use crate::database::value::DbValue;
 use crate::impl_odbc_provider;
 use crate::trnsys::error::TrnSysError;
 use indexmap::IndexSet;
 use odbc_api::buffers::BufferDesc;
 use odbc_api::parameter::InputParameter;
 use odbc_api::sys::{Date, Time, Timestamp};
-use odbc_api::{Connection, ConnectionOptions, Cursor, DataType, Environment, ResultSetMetadata};
+use odbc_api::{Connection, ConnectionOptions, Cursor, DataType, ResultSetMetadata};
 use std::fs;
-use std::sync::{Mutex, MutexGuard};
+use std::sync::{Arc, MutexGuard};
 use strum::IntoEnumIterator;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
+
+/// Maximum number of rows per multi-row insert, the limit of a `VALUES` list of SQL Server.
+const MAX_ROWS_PER_STATEMENT: usize = 1000;
 
 pub trait OdbcProvider<'c>: Send + Sync + SqlDialect {
-    fn set_connection(&mut self, connection: Connection<'c>) -> Result<(), TrnSysError>;
+    fn set_connection(&mut self, connection: Arc<SharedConnection<'c>>) -> Result<(), TrnSysError>;
     fn setup_by_conn_str(
         &mut self,
-        environment: &'c Environment,
+        registry: &ConnectionRegistry<'c>,
         conn_str: &str,
         conn_options: Option<ConnectionOptions>,
     ) -> Result<(), TrnSysError>
     where
         Self: Sized,
     {
-        debug!("Connection String: {}", conn_str);
-        let connection = environment
-            .connect_with_connection_string(conn_str, conn_options.unwrap_or_default())?;
-        self.set_connection(connection)?;
-        Ok(())
-    }
-    #[allow(dead_code)]
-    fn setup_by_dsn(
-        &mut self,
-        environment: &'c Environment,
-        dsn: &str,
-        user: &str,
-        password: &str,
-        conn_options: Option<ConnectionOptions>,
-    ) -> Result<(), TrnSysError>
-    where
-        Self: Sized,
-    {
-        let connection =
-            environment.connect(dsn, user, password, conn_options.unwrap_or_default())?;
+        let connection = registry.connect_by_conn_str(conn_str, conn_options)?;
         self.set_connection(connection)?;
         Ok(())
     }
 
-    fn get_connection(&self) -> Result<MutexGuard<Connection<'c>>, TrnSysError>;
+    fn get_connection(&self) -> Result<MutexGuard<'_, Connection<'c>>, TrnSysError>;
 
-    fn ensure_table(
-        &self,
-        table_name: &str,
-        cols: Vec<ColDef>,
-        creation_extra_cols: Option<Vec<String>>,
-    ) -> Result<(), TrnSysError> {
-        let connection = self.get_connection()?;
+    /// The capabilities of the driver, see [`ProviderCapabilities`].
+    fn get_capabilities(&self) -> ProviderCapabilities;
 
-        let mut col_type_set: IndexSet<ColDef> = cols.into_iter().collect();
+    /// Overrides the capabilities of the dialect, e.g. for a driver lacking features.
+    fn set_capabilities(&mut self, capabilities: ProviderCapabilities);
 
-        // Add predefined columns in the front
-        for (i, meta_col) in MetaCol::iter().enumerate() {
-            col_type_set.insert_before(i, meta_col.col_def());
-        }
-        debug!("table_name: {}", table_name);
-        // Check if table exists
+    fn table_exists(
+        &self,
+        connection: &Connection<'c>,
+        table_name: &str,
+    ) -> Result<bool, TrnSysError> {
         let mut table_list_cursor = connection.tables("", "", table_name, "TABLE")?;
 
         let table_name_col_index = table_list_cursor.find_col_index("TABLE_NAME")?;
@@ -77,7 +59,6 @@ pub trait OdbcProvider<'c>: Send + Sync + SqlDialect {
             table_name_col_index.expect("No TABLE_NAME column found in tables_cursor") as u16;
         debug!("Table Name Index: {}", table_name_index);
 
-        let mut table_exists = false;
         // iterate tables to find if table exists
         while let Some(mut row) = table_list_cursor.next_row()? {
             let mut buf = Vec::new();
@@ -85,104 +66,527 @@ pub trait OdbcProvider<'c>: Send + Sync + SqlDialect {
                 let name = String::from_utf8(buf).unwrap();
                 debug!("Found Table: {}", name);
                 if name == table_name {
-                    table_exists = true;
-                    break;
+                    return Ok(true);
                 }
             } else {
                 debug!("No Table Name Found for Index: {}", table_name_index);
             }
         }
+        Ok(false)
+    }
 
-        debug!("Table exists: {}", table_exists);
-        if table_exists {
-            // try with cursor
-            let mut column_info_cursor = connection.columns("", "", table_name, "")?;
+    /// Like [`OdbcProvider::table_exists`], locking the connection for the check.
+    fn has_table(&self, table_name: &str) -> Result<bool, TrnSysError> {
+        let connection = self.get_connection()?;
+        self.table_exists(&connection, table_name)
+    }
 
-            // The returned cursor has the columns: TABLE_CAT, TABLE_SCHEM, TABLE_NAME,
-            // COLUMN_NAME, DATA_TYPE, TYPE_NAME, COLUMN_SIZE, BUFFER_LENGTH, DECIMAL_DIGITS,
-            // NUM_PREC_RADIX, NULLABLE, REMARKS, COLUMN_DEF, SQL_DATA_TYPE, SQL_DATETIME_SUB, CHAR_OCTET_LENGTH, ORDINAL_POSITION, IS_NULLABLE.
-            // Find the index number of the "COLUMN_NAME" column
-            let column_name_index = column_info_cursor.find_col_index("COLUMN_NAME")?;
+    fn get_table_columns(
+        &self,
+        connection: &Connection<'c>,
+        table_name: &str,
+    ) -> Result<Vec<String>, TrnSysError> {
+        Ok(self
+            .get_table_col_defs(connection, table_name)?
+            .into_iter()
+            .map(|col| col.name)
+            .collect())
+    }
+
+    /// The columns of an existing table, with the types reported by the catalog.
+    /// Meta columns get their definition, including the primary key.
+    fn get_table_col_defs(
+        &self,
+        connection: &Connection<'c>,
+        table_name: &str,
+    ) -> Result<Vec<ColDef>, TrnSysError> {
+        let mut column_info_cursor = connection.columns("", "", table_name, "")?;
 
-            if column_name_index.is_none() {
-                return Err(TrnSysError::GeneralError(
+        // The returned cursor has the columns: TABLE_CAT, TABLE_SCHEM, TABLE_NAME,
+        // COLUMN_NAME, DATA_TYPE, TYPE_NAME, COLUMN_SIZE, BUFFER_LENGTH, DECIMAL_DIGITS,
+        // NUM_PREC_RADIX, NULLABLE, REMARKS, COLUMN_DEF, SQL_DATA_TYPE, SQL_DATETIME_SUB, CHAR_OCTET_LENGTH, ORDINAL_POSITION, IS_NULLABLE.
+        // Find the index number of the "COLUMN_NAME" column
+        let column_name_index =
+            column_info_cursor
+                .find_col_index("COLUMN_NAME")?
+                .ok_or(TrnSysError::GeneralError(
                     "No COLUMN_NAME column found in columns_cursor".to_string(),
-                )
-                .into());
+                ))? as u16;
+        let data_type_index = column_info_cursor.find_col_index("DATA_TYPE")?;
+
+        let mut columns = Vec::new();
+        while let Some(mut row) = column_info_cursor.next_row()? {
+            let mut buf = Vec::new();
+            if row.get_text(column_name_index, &mut buf)? {
+                let name = String::from_utf8(buf).unwrap();
+                let mut sql_type: i16 = 0;
+                if let Some(index) = data_type_index {
+                    row.get_data(index as u16, &mut sql_type)?;
+                }
+                columns.push(recorded_col_def(
+                    &name,
+                    ColDataType::from_sql_type(sql_type),
+                ));
+            }
+        }
+        Ok(columns)
+    }
+
+    fn get_create_table_query(
+        &self,
+        table_name: &str,
+        col_defs: &IndexSet<ColDef>,
+        creation_extra_cols: Vec<String>,
+    ) -> String {
+        let primary_key_str = self.get_primary_key_str(col_defs.iter().collect());
+
+        let cols_def = col_defs
+            .iter()
+            .map(|col| self.get_col_def_str(col))
+            .chain(creation_extra_cols)
+            .chain(vec![primary_key_str])
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", \n");
+
+        format!(
+            r"
+            CREATE TABLE {}
+            (
+            {}
+            );",
+            table_name, cols_def
+        )
+    }
+
+    /// Replaces the table by a new one with the columns `col_defs`,
+    /// copying `copy_cols` of all rows matching `keep_condition`.
+    ///
+    /// Used for drivers that can neither `DELETE` rows nor `ALTER` tables. The rows are copied
+    /// to `{table}_rebuild`, which then replaces the table. With transactional DDL this runs
+    /// in one transaction, otherwise a rebuild interrupted after dropping the table is completed
+    /// from `{table}_rebuild` by the next one.
+    fn rebuild_table(
+        &self,
+        connection: &Connection<'c>,
+        table_name: &str,
+        col_defs: &IndexSet<ColDef>,
+        copy_cols: &[String],
+        keep_condition: Option<String>,
+    ) -> Result<(), TrnSysError> {
+        let temp_table = format!("{}_rebuild", table_name);
+        let where_str = keep_condition
+            .map(|condition| format!(" WHERE {}", condition))
+            .unwrap_or_default();
+        info!("Rebuilding table {}", table_name);
+
+        // leftover of an interrupted rebuild
+        if self.table_exists(connection, &temp_table)? {
+            if self.table_exists(connection, table_name)? {
+                // either of both may be incomplete, so neither is dropped
+                return Err(TrnSysError::GeneralError(format!(
+                    "Table {} is left over from an interrupted rebuild of {}. \
+                    Check both tables and drop the one not needed.",
+                    temp_table, table_name
+                )));
+            }
+            warn!(
+                "Restoring table {} from {}, left over from an interrupted rebuild",
+                table_name, temp_table
+            );
+            let temp_col_defs = self
+                .get_table_col_defs(connection, &temp_table)?
+                .into_iter()
+                .collect::<IndexSet<_>>();
+            self.replace_table(connection, &temp_table, table_name, &temp_col_defs)?;
+        }
+
+        let copy_cols_str = copy_cols
+            .iter()
+            .map(|col| self.format_identifier(col))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let rebuild = || {
+            for query in [
+                self.get_create_table_query(&temp_table, col_defs, vec![]),
+                format!(
+                    "INSERT INTO {} ({}) SELECT {} FROM {}{}",
+                    temp_table, copy_cols_str, copy_cols_str, table_name, where_str
+                ),
+                format!("DROP TABLE {}", table_name),
+            ] {
+                debug!("Rebuild Query: {}", query);
+                connection.execute(&query, ())?;
+            }
+            self.replace_table(connection, &temp_table, table_name, col_defs)
+        };
+        if self.get_capabilities().transactional_ddl {
+            in_transaction(connection, rebuild)
+        } else {
+            rebuild()
+        }
+    }
+
+    /// Moves the table `from` with the columns `col_defs` to the missing table `to`,
+    /// by renaming it or, if the dialect cannot, by copying all rows.
+    ///
+    /// `from` is only dropped once `to` holds as many rows.
+    fn replace_table(
+        &self,
+        connection: &Connection<'c>,
+        from: &str,
+        to: &str,
+        col_defs: &IndexSet<ColDef>,
+    ) -> Result<(), TrnSysError> {
+        if let Some(query) = self.get_rename_table_query(from, to) {
+            debug!("Rebuild Query: {}", query);
+            connection.execute(&query, ())?;
+            return Ok(());
+        }
+        let cols_str = col_defs
+            .iter()
+            .map(|col| self.format_identifier(&col.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        for query in [
+            self.get_create_table_query(to, col_defs, vec![]),
+            format!(
+                "INSERT INTO {} ({}) SELECT {} FROM {}",
+                to, cols_str, cols_str, from
+            ),
+        ] {
+            debug!("Rebuild Query: {}", query);
+            connection.execute(&query, ())?;
+        }
+        let (copied, expected) = (
+            select_count(connection, to, "")?,
+            select_count(connection, from, "")?,
+        );
+        if copied != expected {
+            return Err(TrnSysError::GeneralError(format!(
+                "Copying {} to {} wrote {} of {} rows, {} is kept.",
+                from, to, copied, expected, from
+            )));
+        }
+        let query = format!("DROP TABLE {}", from);
+        debug!("Rebuild Query: {}", query);
+        connection.execute(&query, ())?;
+        Ok(())
+    }
+
+    fn ensure_table(
+        &self,
+        table_name: &str,
+        cols: Vec<ColDef>,
+        creation_extra_cols: Option<Vec<String>>,
+    ) -> Result<(), TrnSysError> {
+        self.ensure_plain_table(table_name, table_col_defs(cols), creation_extra_cols)
+    }
+
+    /// Like [`OdbcProvider::ensure_table`], but without adding the meta columns.
+    ///
+    /// Retried while the database is locked by another process.
+    fn ensure_plain_table(
+        &self,
+        table_name: &str,
+        col_type_set: IndexSet<ColDef>,
+        creation_extra_cols: Option<Vec<String>>,
+    ) -> Result<(), TrnSysError> {
+        with_retry(&format!("Ensuring table {}", table_name), || {
+            self.try_ensure_plain_table(table_name, &col_type_set, creation_extra_cols.clone())
+        })
+    }
+
+    fn try_ensure_plain_table(
+        &self,
+        table_name: &str,
+        col_type_set: &IndexSet<ColDef>,
+        creation_extra_cols: Option<Vec<String>>,
+    ) -> Result<(), TrnSysError> {
+        // The connection stays locked until the table is reconciled,
+        // so units sharing the connection cannot alter the table in between.
+        let connection = self.get_connection()?;
+        let capabilities = self.get_capabilities();
+        debug!("table_name: {}", table_name);
+
+        if let Some(max_len) = capabilities.max_identifier_length {
+            if let Some(name) = std::iter::once(table_name)
+                .chain(col_type_set.iter().map(|col| col.name.as_str()))
+                .find(|name| name.len() > max_len)
+            {
+                return Err(TrnSysError::GeneralError(format!(
+                    "Name '{}' exceeds the maximum identifier length of {}.",
+                    name, max_len
+                )));
             }
-            let column_name_index = column_name_index.unwrap() as u16;
-
-            while let Some(mut row) = column_info_cursor.next_row()? {
-                let mut buf = Vec::new();
-                if row.get_text(column_name_index, &mut buf)? {
-                    let column_name = String::from_utf8(buf).unwrap();
-                    col_type_set.shift_remove(&ColDef::new(
-                        &column_name,
-                        ColDataType::Text,
-                        false,
-                        false,
-                    ));
+        }
+
+        // Check if table exists
+        let table_exists = self.table_exists(&connection, table_name)?;
+
+        debug!("Table exists: {}", table_exists);
+        if table_exists {
+            return self.add_missing_columns(&connection, table_name, col_type_set);
+        }
+
+        // add a new table
+        capabilities.check_num_columns(table_name, col_type_set.len())?;
+
+        let create_table_query = self.get_create_table_query(
+            table_name,
+            col_type_set,
+            creation_extra_cols.unwrap_or_default(),
+        );
+        debug!("Create Table Query: {}", create_table_query);
+        let created = connection.execute(&create_table_query, ()).map(|_| ());
+        match created {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let e = TrnSysError::from(e);
+                if !is_already_exists(&e) {
+                    return Err(e);
                 }
+                // created by another process since the check
+                info!("Table {} was created concurrently", table_name);
+                self.add_missing_columns(&connection, table_name, col_type_set)
             }
+        }
+    }
+
+    /// Adds the columns of `col_type_set` missing in the existing table.
+    fn add_missing_columns(
+        &self,
+        connection: &Connection<'c>,
+        table_name: &str,
+        col_type_set: &IndexSet<ColDef>,
+    ) -> Result<(), TrnSysError> {
+        let capabilities = self.get_capabilities();
+        let existing_cols = self.get_table_columns(connection, table_name)?;
+        let missing_cols = col_type_set
+            .iter()
+            .filter(|col| !existing_cols.contains(&col.name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if missing_cols.is_empty() {
+            return Ok(());
+        }
+        capabilities.check_num_columns(table_name, existing_cols.len() + missing_cols.len())?;
 
+        if capabilities.alter_add_column {
             // add missing columns
-            for col_def in col_type_set {
+            for col_def in missing_cols {
                 let alter_query = format!(
                     "ALTER TABLE {} ADD COLUMN {}",
                     table_name,
                     self.get_col_def_str(&col_def)
                 );
-                connection.execute(&alter_query, ())?;
+                if let Err(e) = connection.execute(&alter_query, ()) {
+                    // added by another process since the check
+                    let e = TrnSysError::from(e);
+                    if !is_already_exists(&e) {
+                        return Err(e);
+                    }
+                }
             }
         } else {
-            // add a new table
-
-            let primary_key_str = self.get_primary_key_str(col_type_set.iter().collect());
-
-            let cols_def = col_type_set
-                .iter()
-                .map(|col| self.get_col_def_str(col))
-                .chain(creation_extra_cols.unwrap_or_default())
-                .chain(vec![primary_key_str])
-                .filter(|s| !s.is_empty())
-                .collect::<Vec<_>>()
-                .join(", \n");
+            let all_cols = self
+                .get_table_col_defs(connection, table_name)?
+                .into_iter()
+                .map(|col| col_type_set.get(&col).cloned().unwrap_or(col))
+                .chain(missing_cols)
+                .collect::<IndexSet<_>>();
+            self.rebuild_table(connection, table_name, &all_cols, &existing_cols, None)?;
+        }
+        Ok(())
+    }
 
-            let create_table_query = format!(
-                r"
-            CREATE TABLE {}
-            (
-            {}
-            );",
-                table_name, cols_def
-            );
-            debug!("Create Table Query: {}", create_table_query);
-            connection.execute(&create_table_query, ())?;
+    /// Creates an index on each of the columns of the table, unless it exists.
+    fn ensure_indexes(&self, table_name: &str, col_names: &[String]) -> Result<(), TrnSysError> {
+        if col_names.is_empty() || !self.support_index() {
+            return Ok(());
+        }
+        let max_identifier_length = self.get_capabilities().max_identifier_length;
+        for col_name in col_names {
+            let index_name = self.get_index_name(table_name, col_name);
+            if max_identifier_length.is_some_and(|max_len| index_name.len() > max_len) {
+                warn!(
+                    "Skipping index {}, its name exceeds the maximum identifier length.",
+                    index_name
+                );
+                continue;
+            }
+            with_retry(&format!("Creating index {}", index_name), || {
+                let connection = self.get_connection()?;
+                if let Some(query) = self.get_index_exists_query(table_name, &index_name) {
+                    if select_count_query(&connection, &query)? > 0 {
+                        return Ok(());
+                    }
+                }
+                let query = self.get_create_index_query(table_name, &index_name, col_name);
+                debug!("Create Index Query: {}", query);
+                let created = connection.execute(&query, ()).map(|_| ());
+                match created.map_err(TrnSysError::from) {
+                    Err(e) if is_already_exists(&e) => Ok(()),
+                    result => result,
+                }
+            })?;
         }
         Ok(())
     }
 
+    /// Ensures the unique key the upsert statement of the dialect relies on,
+    /// adding a unique index on the key columns if the table has none,
+    /// e.g. as it was created before `Step` was part of the primary key.
+    fn ensure_upsert_key(&self, table_name: &str, key_cols: &[String]) -> Result<(), TrnSysError> {
+        let Some(query) = self.get_unique_key_exists_query(table_name, key_cols) else {
+            return Ok(());
+        };
+        if key_cols.is_empty() {
+            return Ok(());
+        }
+        let index_name = self.get_index_name(table_name, &key_cols.join("_"));
+        with_retry(&format!("Creating index {}", index_name), || {
+            let connection = self.get_connection()?;
+            if select_count_query(&connection, &query)? > 0 {
+                return Ok(());
+            }
+            let create_query =
+                self.get_create_unique_index_query(table_name, &index_name, key_cols);
+            info!(
+                "Table {} has no unique key on ({}) for InsertMode=Upsert, adding one: {}",
+                table_name,
+                key_cols.join(", "),
+                create_query
+            );
+            match connection.execute(&create_query, ()).map(|_| ()) {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    let e = TrnSysError::from(e);
+                    if is_already_exists(&e) {
+                        return Ok(());
+                    }
+                    // e.g. rows with the same key, which the upsert could not tell apart
+                    Err(TrnSysError::GeneralError(format!(
+                        "InsertMode=Upsert needs a unique key on ({}) of table {}, \
+                        adding it failed: {}. Use a new table or InsertMode=Insert.",
+                        key_cols.join(", "),
+                        table_name,
+                        e
+                    )))
+                }
+            }
+        })
+    }
+
+    /// The condition selecting the rows of the variant.
+    fn variant_condition(&self, variant_name: &str) -> String {
+        format!(
+            "{} = {}",
+            self.format_identifier(MetaCol::Variant.as_str()),
+            self.format_text_literal(variant_name)
+        )
+    }
+
     fn remove_variant(&self, table_name: &str, variant_name: &str) -> Result<(), TrnSysError> {
+        self.delete_rows(table_name, self.variant_condition(variant_name))?;
+        info!("Variant removed.");
+        Ok(())
+    }
+
+    /// Deletes the rows matching the `WHERE` condition,
+    /// by rebuilding the table if the driver cannot `DELETE`.
+    fn delete_rows(&self, table_name: &str, condition: String) -> Result<(), TrnSysError> {
+        with_retry(&format!("Deleting from {}", table_name), || {
+            self.try_delete_rows(table_name, &condition)
+        })
+    }
+
+    fn try_delete_rows(&self, table_name: &str, condition: &str) -> Result<(), TrnSysError> {
         let connection = self.get_connection()?;
+        if self.get_capabilities().delete {
+            let query = format!("DELETE FROM {} WHERE {}", table_name, condition);
+            info!("Delete Query: {}", query);
+            connection.execute(&query, ())?;
+        } else {
+            // rebuilding copies the whole table, so it is skipped if no rows match;
+            // a missing table is left to the rebuild, which restores an interrupted one
+            let where_str = format!(" WHERE {}", condition);
+            if self.table_exists(&connection, table_name)?
+                && select_count(&connection, table_name, &where_str)? == 0
+            {
+                return Ok(());
+            }
+            let col_defs = self
+                .get_table_col_defs(&connection, table_name)?
+                .into_iter()
+                .collect::<IndexSet<_>>();
+            let existing_cols = col_defs
+                .iter()
+                .map(|col| col.name.clone())
+                .collect::<Vec<_>>();
+            self.rebuild_table(
+                &connection,
+                table_name,
+                &col_defs,
+                &existing_cols,
+                Some(format!("NOT ({})", condition)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sets the columns to the values in all rows matching the `WHERE` condition.
+    fn update_rows(
+        &self,
+        table_name: &str,
+        cols: Vec<(String, DbValue)>,
+        condition: String,
+    ) -> Result<(), TrnSysError> {
+        let assignments = cols
+            .iter()
+            .map(|(name, _)| format!("{} = ?", self.format_identifier(name)))
+            .collect::<Vec<_>>()
+            .join(", ");
         let query = format!(
-            "DELETE FROM {} WHERE {} = '{}'",
-            table_name,
-            self.format_identifier(MetaCol::Variant.as_str()),
-            variant_name
+            "UPDATE {} SET {} WHERE {}",
+            table_name, assignments, condition
         );
-        info!("Remove Variant Query: {}", query);
-        connection.execute(&query, ())?;
-        info!("Variant removed.");
+        debug!("Update Query: {}", query);
+        let params = cols
+            .iter()
+            .map(|(_, value)| value.to_parameter())
+            .collect::<Vec<_>>();
+        with_retry(&format!("Updating {}", table_name), || {
+            let connection = self.get_connection()?;
+            connection.execute(&query, params.as_slice())?;
+            Ok(())
+        })
+    }
+
+    /// Frees the space of deleted rows, if the dialect has a statement for it.
+    fn compact(&self) -> Result<(), TrnSysError> {
+        if let Some(query) = self.get_compact_query() {
+            let connection = self.get_connection()?;
+            info!("Compacting database: {}", query);
+            connection.execute(&query, ())?;
+        }
         Ok(())
     }
 
+    /// Counts the rows of the table, optionally filtered by a `WHERE` condition.
+    fn count_rows(&self, table_name: &str, condition: Option<String>) -> Result<i64, TrnSysError> {
+        let connection = self.get_connection()?;
+        let where_str = condition
+            .map(|condition| format!(" WHERE {}", condition))
+            .unwrap_or_default();
+        select_count(&connection, table_name, &where_str)
+    }
+
     fn insert_data(
         &self,
         table: &str,
         cols: Vec<(String, Box<dyn InputParameter>)>,
     ) -> Result<(), TrnSysError> {
-        let conn = self.get_connection()?;
-
         let col_names = cols
             .iter()
             .map(|(name, _)| self.format_identifier(name.as_str()))
@@ -195,45 +599,120 @@ pub trait OdbcProvider<'c>: Send + Sync + SqlDialect {
             table, col_names, placeholders
         );
         debug!("Insert Query: {}", query);
-        let mut statement = conn.prepare(&query)?;
         let params = cols.into_iter().map(|(_, param)| param).collect::<Vec<_>>();
-        statement.execute(params.as_slice())?;
-
-        Ok(())
+        with_retry(&format!("Inserting into {}", table), || {
+            let conn = self.get_connection()?;
+            conn.execute(&query, params.as_slice())?;
+            Ok(())
+        })
     }
 
     fn batch_insert_data(
         &self,
         table: &str,
         col_names: Vec<String>,
-        rows: Vec<Vec<Box<dyn InputParameter>>>,
+        rows: Vec<Vec<DbValue>>,
     ) -> Result<(), TrnSysError> {
-        let conn = self.get_connection()?;
+        self.write_rows(table, col_names, rows, InsertMode::Insert, &[])
+    }
 
-        let col_name_field = col_names
-            .iter()
-            .map(|name| self.format_identifier(name.as_str()))
-            .collect::<Vec<_>>()
-            .join(", ");
-        let placeholders = rows
-            .first()
-            .expect("empty row")
+    /// Inserts the rows, replacing existing rows with the same values in the `key_cols`
+    /// in [`InsertMode::Upsert`].
+    ///
+    /// Dialects without an upsert statement delete the existing rows before inserting.
+    fn write_rows(
+        &self,
+        table: &str,
+        col_names: Vec<String>,
+        rows: Vec<Vec<DbValue>>,
+        mode: InsertMode,
+        key_cols: &[String],
+    ) -> Result<(), TrnSysError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let capabilities = self.get_capabilities();
+
+        // the primary key columns among the written columns
+        let key_cols = key_cols
             .iter()
-            .map(|_| "?")
-            .collect::<Vec<_>>()
-            .join(", ");
+            .filter(|name| col_names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        let upsert =
+            mode == InsertMode::Upsert && self.support_primary_key() && !key_cols.is_empty();
 
-        let query = format!(
-            "INSERT INTO {} ({}) VALUES ({})",
-            table, col_name_field, placeholders
-        );
+        let has_upsert_query = upsert
+            && self
+                .get_upsert_query(table, &col_names, &key_cols, 1)
+                .is_some();
+        let delete_query = (upsert && !has_upsert_query && capabilities.delete).then(|| {
+            format!(
+                "DELETE FROM {} WHERE {}",
+                table,
+                key_cols
+                    .iter()
+                    .map(|name| format!("{} = ?", self.format_identifier(name)))
+                    .collect::<Vec<_>>()
+                    .join(" AND ")
+            )
+        });
+        // the statement writing the given number of rows
+        let statement = |num_rows: usize| {
+            has_upsert_query
+                .then(|| self.get_upsert_query(table, &col_names, &key_cols, num_rows))
+                .flatten()
+                .unwrap_or_else(|| self.get_insert_query(table, &col_names, num_rows))
+        };
+        let query = statement(1);
         debug!("Insert Query: {}", query);
 
-        let mut statement = conn.prepare(&query)?;
-        for row in rows {
-            statement.execute(row.as_slice())?;
-        }
-        Ok(())
+        // a parameter array may fail partway, so it is only used if a retry writes all rows again
+        let bulk_params = capabilities.bulk_params && (capabilities.transactions || upsert);
+        // without parameter arrays, several rows are sent per statement if the dialect allows
+        let rows_per_statement =
+            if delete_query.is_none() && !bulk_params && self.support_multi_row_values() {
+                capabilities
+                    .max_params
+                    .map_or(MAX_ROWS_PER_STATEMENT, |max_params| {
+                        max_params / col_names.len().max(1)
+                    })
+                    .clamp(1, MAX_ROWS_PER_STATEMENT)
+            } else {
+                1
+            };
+
+        // inserts the rows from `written` on, counting the rows written
+        let insert = |conn: &Connection<'c>, written: &mut usize| {
+            if let Some(delete_query) = delete_query.as_ref() {
+                let key_indices = key_cols
+                    .iter()
+                    .filter_map(|key| col_names.iter().position(|name| name == key))
+                    .collect::<Vec<_>>();
+                replace_row_by_row(conn, delete_query, &key_indices, &query, &rows)
+            } else if bulk_params {
+                insert_param_array(conn, &query, &rows)
+            } else if rows_per_statement > 1 {
+                insert_multi_row(conn, &statement, rows_per_statement, &rows, written)
+            } else {
+                insert_row_by_row(conn, &query, &rows, written)
+            }
+        };
+        // rows inserted without a transaction stay after a failure, so a retry continues
+        // from the failed row. A failed transaction is rolled back and upserts replace
+        // the rows written before a failure, so all rows are written again on a retry.
+        let mut written = 0;
+        with_retry(&format!("Inserting into {}", table), || {
+            // locked per attempt, so other units are not blocked during the backoff
+            let conn = self.get_connection()?;
+            if !capabilities.transactions {
+                if upsert {
+                    written = 0;
+                }
+                return insert(&conn, &mut written);
+            }
+            in_transaction(&conn, || insert(&conn, &mut 0))
+        })
     }
 
     fn query_data(
@@ -340,14 +819,205 @@ pub trait OdbcProvider<'c>: Send + Sync + SqlDialect {
     }
 }
 
+/// Runs `f` in a transaction, committing if it succeeds and rolling back otherwise.
+///
+/// Autocommit is switched back on in any case, as the connection is shared with other units.
+fn in_transaction<T>(
+    conn: &Connection<'_>,
+    f: impl FnOnce() -> Result<T, TrnSysError>,
+) -> Result<T, TrnSysError> {
+    conn.set_autocommit(false)?;
+    let result = f().and_then(|value| {
+        conn.commit()?;
+        Ok(value)
+    });
+    if let Err(e) = &result {
+        // the error of the transaction is the one to report
+        if let Err(rollback_error) = conn.rollback() {
+            error!("Rolling back after '{}' failed: {}", e, rollback_error);
+        }
+    }
+    let restored = conn.set_autocommit(true);
+    match (result, restored) {
+        (Ok(value), Ok(())) => Ok(value),
+        (Ok(_), Err(e)) => Err(e.into()),
+        (Err(e), restored) => {
+            if let Err(restore_error) = restored {
+                error!(
+                    "Restoring autocommit after '{}' failed: {}",
+                    e, restore_error
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Counts the rows of the table, `where_str` being empty or a ` WHERE` clause.
+fn select_count(
+    connection: &Connection<'_>,
+    table_name: &str,
+    where_str: &str,
+) -> Result<i64, TrnSysError> {
+    select_count_query(
+        connection,
+        &format!("SELECT COUNT(*) FROM {}{}", table_name, where_str),
+    )
+}
+
+/// The single integer the `SELECT COUNT(*)` query returns.
+fn select_count_query(connection: &Connection<'_>, query: &str) -> Result<i64, TrnSysError> {
+    let mut count: i64 = 0;
+    if let Some(mut cursor) = connection.execute(query, ())? {
+        if let Some(mut row) = cursor.next_row()? {
+            row.get_data(1, &mut count)?;
+        }
+    }
+    Ok(count)
+}
+
+/// The definition of an existing column of type `data_type`.
+/// Meta columns keep their definition, so rebuilt tables keep their primary key.
+fn recorded_col_def(name: &str, data_type: ColDataType) -> ColDef {
+    MetaCol::iter()
+        .find(|meta_col| meta_col.as_str() == name)
+        .map(|meta_col| meta_col.col_def())
+        .unwrap_or_else(|| ColDef::new(name, data_type, false, false))
+}
+
+/// Executes the insert statement once per row, from row `written` on.
+fn insert_row_by_row(
+    conn: &Connection<'_>,
+    query: &str,
+    rows: &[Vec<DbValue>],
+    written: &mut usize,
+) -> Result<(), TrnSysError> {
+    let mut statement = conn.prepare(query)?;
+    for row in &rows[*written..] {
+        let params = row.iter().map(|v| v.to_parameter()).collect::<Vec<_>>();
+        statement.execute(params.as_slice())?;
+        *written += 1;
+    }
+    Ok(())
+}
+
+/// Inserts the rows from row `written` on in statements of `rows_per_statement` rows,
+/// the last one with the rest.
+fn insert_multi_row(
+    conn: &Connection<'_>,
+    statement: &dyn Fn(usize) -> String,
+    rows_per_statement: usize,
+    rows: &[Vec<DbValue>],
+    written: &mut usize,
+) -> Result<(), TrnSysError> {
+    let mut full_statement = None;
+    for chunk in rows[*written..].chunks(rows_per_statement) {
+        let params = chunk
+            .iter()
+            .flatten()
+            .map(|v| v.to_parameter())
+            .collect::<Vec<_>>();
+        if chunk.len() == rows_per_statement {
+            let prepared = match full_statement.as_mut() {
+                Some(prepared) => prepared,
+                None => full_statement.insert(conn.prepare(&statement(rows_per_statement))?),
+            };
+            prepared.execute(params.as_slice())?;
+        } else {
+            conn.execute(&statement(chunk.len()), params.as_slice())?;
+        }
+        *written += chunk.len();
+    }
+    Ok(())
+}
+
+/// Deletes the row with the key of each row before inserting it,
+/// for dialects without an upsert statement.
+fn replace_row_by_row(
+    conn: &Connection<'_>,
+    delete_query: &str,
+    key_indices: &[usize],
+    insert_query: &str,
+    rows: &[Vec<DbValue>],
+) -> Result<(), TrnSysError> {
+    let mut delete = conn.prepare(delete_query)?;
+    let mut insert = conn.prepare(insert_query)?;
+    for row in rows {
+        let keys = key_indices
+            .iter()
+            .map(|i| row[*i].to_parameter())
+            .collect::<Vec<_>>();
+        delete.execute(keys.as_slice())?;
+        let params = row.iter().map(|v| v.to_parameter()).collect::<Vec<_>>();
+        insert.execute(params.as_slice())?;
+    }
+    Ok(())
+}
+
+/// Executes the insert statement once for all rows, binding each column as a parameter array.
+fn insert_param_array(
+    conn: &Connection<'_>,
+    query: &str,
+    rows: &[Vec<DbValue>],
+) -> Result<(), TrnSysError> {
+    let num_cols = rows.first().map(|row| row.len()).unwrap_or_default();
+    let descs = (0..num_cols)
+        .map(|col| {
+            rows.iter()
+                .filter_map(|row| row[col].buffer_desc())
+                .reduce(|a, b| match (a, b) {
+                    (
+                        BufferDesc::Text { max_str_len: a_len },
+                        BufferDesc::Text { max_str_len: b_len },
+                    ) => BufferDesc::Text {
+                        max_str_len: a_len.max(b_len),
+                    },
+                    (a, _) => a,
+                })
+                .unwrap_or(BufferDesc::F64 { nullable: true })
+        })
+        .collect::<Vec<_>>();
+
+    let mut inserter = conn
+        .prepare(query)?
+        .into_column_inserter(rows.len(), descs.clone())?;
+    inserter.set_num_rows(rows.len());
+    for (col, desc) in descs.iter().enumerate() {
+        let column = inserter.column_mut(col);
+        match desc {
+            BufferDesc::Text { .. } => {
+                let mut column = column.as_text_view().expect("text buffer");
+                for (i, row) in rows.iter().enumerate() {
+                    column.set_cell(i, row[col].as_text().map(|text| text.as_bytes()));
+                }
+            }
+            BufferDesc::I64 { .. } => {
+                let mut column = column.as_nullable_slice::<i64>().expect("i64 buffer");
+                for (i, row) in rows.iter().enumerate() {
+                    column.set_cell(i, row[col].as_i64());
+                }
+            }
+            _ => {
+                let mut column = column.as_nullable_slice::<f64>().expect("f64 buffer");
+                for (i, row) in rows.iter().enumerate() {
+                    column.set_cell(i, row[col].as_f64());
+                }
+            }
+        }
+    }
+    inserter.execute()?;
+    Ok(())
+}
+
 pub(crate) trait FileDbProvider<'c>: OdbcProvider<'c> {
     fn get_template(&self) -> Result<TemplateFile, TrnSysError>;
 
     fn get_driver_name(&self) -> String;
 
+    /// Connects to the database file at `db_path`, whose placeholders are expanded already.
     fn setup_by_path(
         &mut self,
-        environment: &'c Environment,
+        registry: &ConnectionRegistry<'c>,
         db_path: &str,
         conn_options: Option<ConnectionOptions>,
     ) -> Result<(), TrnSysError>
@@ -356,30 +1026,102 @@ pub(crate) trait FileDbProvider<'c>: OdbcProvider<'c> {
     {
         let db_path_str = clean_and_ensure_path(db_path)?;
         debug!("DB Path: {:?}", db_path_str);
-        self.ensure_file_exists(&db_path_str)?;
+        let new_file = self.ensure_file_exists(&db_path_str)?;
         let driver_name = self.get_driver_name();
         let connection_string = format!("Driver={{{}}};DBQ={};", driver_name, &db_path_str);
-        self.setup_by_conn_str(environment, &connection_string, conn_options)?;
+        self.set_connection(
+            registry.connect_by_expanded_conn_str(&connection_string, conn_options)?,
+        )?;
+        self.after_connect(new_file)?;
         Ok(())
     }
 
-    fn ensure_file_exists(&self, db_path: &str) -> Result<(), TrnSysError> {
-        let file_exists = fs::exists(db_path)?;
-        if !file_exists {
-            info!("Creating file: {}", db_path);
-            self.get_template()?.create_file(db_path)?;
+    /// Called once connected to the file, e.g. to set connection options of the driver.
+    fn after_connect(&self, _new_file: bool) -> Result<(), TrnSysError> {
+        Ok(())
+    }
+
+    /// Creates the file from the template if it does not exist yet.
+    /// Returns whether the file was created.
+    fn ensure_file_exists(&self, db_path: &str) -> Result<bool, TrnSysError> {
+        let created = !fs::exists(db_path)? && self.get_template()?.create_file(db_path)?;
+        if created {
+            info!("Created file: {}", db_path);
         } else {
             info!("File already exists: {}", db_path);
         }
-        Ok(())
+        Ok(created)
     }
 }
 
 pub struct OdbcProviderImpl<'c> {
-    connection: Option<Mutex<Connection<'c>>>,
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
 }
 
-impl SqlDialect for OdbcProviderImpl<'_> {}
+impl SqlDialect for OdbcProviderImpl<'_> {
+    // SQL Server accepts 2100 parameters per request
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            max_params: Some(2100),
+            ..Default::default()
+        }
+    }
+
+    fn get_binary_type(&self) -> String {
+        "VARBINARY(MAX)".to_string()
+    }
+
+    // MERGE of SQL Server, HOLDLOCK prevents concurrent inserts of the same key
+    fn get_upsert_query(
+        &self,
+        table_name: &str,
+        col_names: &[String],
+        key_cols: &[String],
+        num_rows: usize,
+    ) -> Option<String> {
+        let cols = col_names
+            .iter()
+            .map(|name| self.format_identifier(name))
+            .collect::<Vec<_>>();
+        let on = key_cols
+            .iter()
+            .map(|name| {
+                let name = self.format_identifier(name);
+                format!("target.{} = source.{}", name, name)
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let updates = col_names
+            .iter()
+            .filter(|name| !key_cols.contains(name))
+            .map(|name| {
+                let name = self.format_identifier(name);
+                format!("{} = source.{}", name, name)
+            })
+            .collect::<Vec<_>>();
+        let when_matched = if updates.is_empty() {
+            String::new()
+        } else {
+            format!(" WHEN MATCHED THEN UPDATE SET {}", updates.join(", "))
+        };
+        Some(format!(
+            "MERGE INTO {} WITH (HOLDLOCK) AS target \
+            USING (VALUES {}) AS source ({}) ON {}{} \
+            WHEN NOT MATCHED THEN INSERT ({}) VALUES ({});",
+            table_name,
+            values_placeholders(col_names.len(), num_rows),
+            cols.join(", "),
+            on,
+            when_matched,
+            cols.join(", "),
+            cols.iter()
+                .map(|name| format!("source.{}", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
 
 impl_odbc_provider!(OdbcProviderImpl);
 
@@ -387,6 +1129,7 @@ impl_odbc_provider!(OdbcProviderImpl);
 mod tests {
     use super::*;
     use crate::database::ms_access::MsAccessProvider;
+    use odbc_api::Environment;
 
     use fs;
 
@@ -398,6 +1141,9 @@ mod tests {
         Environment::new().unwrap()
     });
 
+    static CONNECTIONS: LazyLock<ConnectionRegistry> =
+        LazyLock::new(|| ConnectionRegistry::new(&ENVIRONMENT));
+
     #[traced_test]
     #[test]
     fn test_create_connection() {
@@ -408,7 +1154,7 @@ mod tests {
         {
             let mut ms_access = MsAccessProvider::new();
             ms_access
-                .setup_by_path(&ENVIRONMENT, db_path, None)
+                .setup_by_path(&CONNECTIONS, db_path, None)
                 .unwrap();
             assert!(ms_access.get_connection().is_ok());
         }
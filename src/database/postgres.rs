@@ -1,20 +1,32 @@
 use super::odbc::OdbcProvider;
 use super::*;
-use crate::database::dialect::SqlDialect;
+use crate::database::dialect::{
+    on_conflict_upsert_query, text_list, ProviderCapabilities, SqlDialect,
+};
+use crate::database::registry::SharedConnection;
 use crate::impl_odbc_provider;
 use odbc_api::Connection;
-use std::sync::Mutex;
+use std::sync::Arc;
 
 pub struct PostgreSQLProvider<'c> {
-    connection: Option<Mutex<Connection<'c>>>,
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
 }
 
 impl SqlDialect for PostgreSQLProvider<'_> {
+    fn get_rename_table_query(&self, from: &str, to: &str) -> Option<String> {
+        Some(format!("ALTER TABLE {} RENAME TO {}", from, to))
+    }
+
     // Override decimal type to use PostgreSQL's DOUBLE PRECISION
     fn get_decimal_type(&self) -> String {
         "DOUBLE PRECISION".to_string()
     }
 
+    fn get_binary_type(&self) -> String {
+        "BYTEA".to_string()
+    }
+
     // PostgreSQL uses TIMESTAMP rather than DATETIME
     fn get_datetime_type(&self) -> String {
         "TIMESTAMP".to_string()
@@ -24,6 +36,47 @@ impl SqlDialect for PostgreSQLProvider<'_> {
     fn format_identifier(&self, identifier: &str) -> String {
         format!("\"{}\"", identifier.replace("\"", "\"\""))
     }
+
+    // Since PostgreSQL 9.5
+    fn support_index_if_not_exists(&self) -> bool {
+        true
+    }
+
+    // unquoted table names are folded to lower case by the cast as when created
+    fn get_unique_key_exists_query(&self, table_name: &str, key_cols: &[String]) -> Option<String> {
+        Some(format!(
+            "SELECT COUNT(*) FROM pg_index i WHERE i.indrelid = {}::regclass AND i.indisunique \
+            AND i.indnatts = {} AND (SELECT COUNT(*) FROM pg_attribute a \
+            WHERE a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) AND a.attname IN ({})) = {}",
+            self.format_text_literal(table_name),
+            key_cols.len(),
+            text_list(self, key_cols),
+            key_cols.len()
+        ))
+    }
+
+    fn get_upsert_query(
+        &self,
+        table_name: &str,
+        col_names: &[String],
+        key_cols: &[String],
+        num_rows: usize,
+    ) -> Option<String> {
+        Some(on_conflict_upsert_query(
+            self, table_name, col_names, key_cols, num_rows,
+        ))
+    }
+
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            transactional_ddl: true,
+            max_columns: Some(1600),
+            max_identifier_length: Some(63),
+            // the parameter count of the wire protocol is a 16 bit integer
+            max_params: Some(32767),
+            ..Default::default()
+        }
+    }
 }
 
 impl_odbc_provider!(PostgreSQLProvider);
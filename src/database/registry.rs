@@ -0,0 +1,205 @@
+use crate::database::secret::{expand_placeholders, redact_secrets};
+use crate::trnsys::error::TrnSysError;
+use odbc_api::{Connection, ConnectionOptions, Environment};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use tracing::{debug, info};
+
+/// Keys whose values are compared case-insensitively when normalizing connection strings.
+const CASE_INSENSITIVE_KEYS: [&str; 2] = ["driver", "dsn"];
+
+/// Keys whose values are file paths, compared as canonical paths when normalizing connection strings.
+const PATH_KEYS: [&str; 1] = ["dbq"];
+
+/// An open connection, shared by all units writing to the same database.
+///
+/// Each unit holds one reference; the connection is closed when the last unit releases it.
+/// Units lock the connection for each operation, so DDL (e.g. the check-and-alter of
+/// `ensure_table`) on a shared table is done by one unit at a time.
+pub struct SharedConnection<'c> {
+    key: String,
+    connection: Mutex<Connection<'c>>,
+}
+
+impl<'c> SharedConnection<'c> {
+    pub fn lock(&self) -> Result<MutexGuard<'_, Connection<'c>>, TrnSysError> {
+        self.connection
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))
+    }
+}
+
+impl Drop for SharedConnection<'_> {
+    fn drop(&mut self) {
+        info!("Closing connection {}", redact_secrets(&self.key));
+    }
+}
+
+/// Process-wide registry of open connections, keyed by normalized connection string.
+pub struct ConnectionRegistry<'c> {
+    environment: &'c Environment,
+    connections: Mutex<HashMap<String, Weak<SharedConnection<'c>>>>,
+}
+
+impl<'c> ConnectionRegistry<'c> {
+    pub fn new(environment: &'c Environment) -> Self {
+        ConnectionRegistry {
+            environment,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the open connection for the connection string, or connects if there is none.
+    /// Placeholders in the connection string are expanded before connecting.
+    pub fn connect_by_conn_str(
+        &self,
+        conn_str: &str,
+        conn_options: Option<ConnectionOptions>,
+    ) -> Result<Arc<SharedConnection<'c>>, TrnSysError> {
+        debug!("Connection String: {}", redact_secrets(conn_str));
+        self.connect_by_expanded_conn_str(&expand_placeholders(conn_str)?, conn_options)
+    }
+
+    /// Like [`ConnectionRegistry::connect_by_conn_str`], for a connection string
+    /// whose placeholders are expanded already, e.g. one built from an expanded file path.
+    pub fn connect_by_expanded_conn_str(
+        &self,
+        conn_str: &str,
+        conn_options: Option<ConnectionOptions>,
+    ) -> Result<Arc<SharedConnection<'c>>, TrnSysError> {
+        let key = normalize_conn_str(conn_str);
+        self.get_or_connect(key, |environment| {
+            Ok(environment
+                .connect_with_connection_string(conn_str, conn_options.unwrap_or_default())?)
+        })
+    }
+
+    /// Returns the open connection for the data source (DSN), or connects if there is none.
+    pub fn connect_by_dsn(
+        &self,
+        dsn: &str,
+        user: &str,
+        password: &str,
+        conn_options: Option<ConnectionOptions>,
+    ) -> Result<Arc<SharedConnection<'c>>, TrnSysError> {
+        debug!("DSN: {}, User: {}", dsn, user);
+        let dsn = expand_placeholders(dsn)?;
+        let user = expand_placeholders(user)?;
+        let password = expand_placeholders(password)?;
+        let key = normalize_conn_str(&format!("DSN={};UID={}", dsn, user));
+        self.get_or_connect(key, |environment| {
+            Ok(environment.connect(&dsn, &user, &password, conn_options.unwrap_or_default())?)
+        })
+    }
+
+    fn get_or_connect(
+        &self,
+        key: String,
+        connect: impl FnOnce(&'c Environment) -> Result<Connection<'c>, TrnSysError>,
+    ) -> Result<Arc<SharedConnection<'c>>, TrnSysError> {
+        if let Some(shared) = self.get_open(&key)? {
+            return Ok(shared);
+        }
+
+        // connect without holding the registry, so a slow server does not block other units
+        let connection = connect(self.environment)?;
+
+        let mut connections = self.lock_connections()?;
+        if let Some(shared) = connections.get(&key).and_then(|c| c.upgrade()) {
+            // another unit connected meanwhile, the new connection is closed
+            return Ok(shared);
+        }
+        let shared = Arc::new(SharedConnection {
+            key: key.clone(),
+            connection: Mutex::new(connection),
+        });
+        connections.insert(key, Arc::downgrade(&shared));
+        Ok(shared)
+    }
+
+    /// The open connection for the key, if another unit holds it.
+    fn get_open(&self, key: &str) -> Result<Option<Arc<SharedConnection<'c>>>, TrnSysError> {
+        let mut connections = self.lock_connections()?;
+        // forget connections released by all units
+        connections.retain(|_, connection| connection.strong_count() > 0);
+
+        let shared = connections.get(key).and_then(|c| c.upgrade());
+        if let Some(shared) = shared.as_ref() {
+            info!(
+                "Sharing connection {} with {} other unit(s).",
+                redact_secrets(key),
+                Arc::strong_count(shared) - 1
+            );
+        }
+        Ok(shared)
+    }
+
+    fn lock_connections(
+        &self,
+    ) -> Result<MutexGuard<'_, HashMap<String, Weak<SharedConnection<'c>>>>, TrnSysError> {
+        self.connections
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))
+    }
+}
+
+/// Normalizes a connection string, so that equivalent strings map to the same connection:
+/// keys are lowercased, pairs are sorted and whitespace around keys and values is removed.
+/// Drivers and DSNs are compared case-insensitively, existing files by their canonical path,
+/// so the same file is matched however it is written, but `Data.db` and `data.db` are
+/// only one file where the file system says so.
+pub fn normalize_conn_str(conn_str: &str) -> String {
+    let mut pairs = conn_str
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+            let value = if CASE_INSENSITIVE_KEYS.contains(&key.as_str()) {
+                value.to_lowercase()
+            } else if PATH_KEYS.contains(&key.as_str()) {
+                std::fs::canonicalize(value)
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| value.to_string())
+            } else {
+                value.to_string()
+            };
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_conn_str() {
+        assert_eq!(
+            normalize_conn_str("Driver={SQLite3 ODBC Driver};DBQ=C:\\Sim\\Result.db;"),
+            normalize_conn_str(" dbq = C:\\Sim\\Result.db ; DRIVER={sqlite3 odbc driver}")
+        );
+        assert_ne!(
+            normalize_conn_str("Driver={SQLite3 ODBC Driver};DBQ=/sim/Data.db"),
+            normalize_conn_str("Driver={SQLite3 ODBC Driver};DBQ=/sim/data.db")
+        );
+
+        let dir = std::env::temp_dir();
+        let file = dir.join("registry_test.db");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(
+            normalize_conn_str(&format!("DBQ={}", file.display())),
+            normalize_conn_str(&format!(
+                "DBQ={}",
+                dir.join(".").join("registry_test.db").display()
+            ))
+        );
+        std::fs::remove_file(&file).unwrap();
+        assert_ne!(
+            normalize_conn_str("Server=db;UID=a;PWD=Secret"),
+            normalize_conn_str("Server=db;UID=a;PWD=secret")
+        );
+    }
+}
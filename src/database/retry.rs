@@ -0,0 +1,203 @@
+use crate::trnsys::error::TrnSysError;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Number of attempts of a statement failing on a lock before giving up.
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Delay after the first failed attempt, doubled on every further attempt.
+const BASE_DELAY: Duration = Duration::from_millis(100);
+
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Access (Jet/ACE) error numbers of locked files, tables and records.
+const ACCESS_LOCK_ERRORS: [i32; 6] = [3006, 3008, 3050, 3188, 3211, 3218];
+
+/// SQLSTATEs of serialization failures, deadlocks and lock timeouts.
+const BUSY_STATES: [&str; 3] = ["40001", "40P01", "HYT00"];
+
+/// SQLSTATEs of tables and columns that already exist.
+const EXISTS_STATES: [&str; 4] = ["42S01", "42S21", "42P07", "42701"];
+
+/// Number of statements retried on a lock in this process.
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// The number of statements retried on a lock so far, to detect waiting for other connections.
+pub fn retry_count() -> u64 {
+    RETRIES.load(Ordering::Relaxed)
+}
+
+/// The diagnostic record of an ODBC error as text, including SQLSTATE and native error.
+fn odbc_record(error: &TrnSysError) -> Option<(String, i32, String)> {
+    error.odbc_record().map(|record| {
+        (
+            record.state.as_str().to_string(),
+            record.native_error,
+            record.to_string().to_lowercase(),
+        )
+    })
+}
+
+/// Whether the statement failed because another connection holds a lock,
+/// so it may succeed when retried.
+pub fn is_busy(error: &TrnSysError) -> bool {
+    let Some((state, native_error, message)) = odbc_record(error) else {
+        return false;
+    };
+    BUSY_STATES.contains(&state.as_str())
+        || ACCESS_LOCK_ERRORS.contains(&native_error)
+        || [
+            "database is locked",
+            "database table is locked",
+            "currently locked",
+        ]
+        .iter()
+        .any(|text| message.contains(text))
+}
+
+/// Whether the statement failed because the table, column or index to create already exists,
+/// e.g. as another process created it in the meantime.
+pub fn is_already_exists(error: &TrnSysError) -> bool {
+    let Some((state, _, message)) = odbc_record(error) else {
+        return false;
+    };
+    EXISTS_STATES.contains(&state.as_str())
+        || message.contains("already exists")
+        || message.contains("duplicate column")
+        || message.contains("already has an index")
+}
+
+/// Runs `f`, retrying with exponential backoff while it fails on a lock.
+pub fn with_retry<T>(
+    what: &str,
+    mut f: impl FnMut() -> Result<T, TrnSysError>,
+) -> Result<T, TrnSysError> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < MAX_ATTEMPTS && is_busy(&e) => {
+                let delay = backoff(attempt);
+                warn!(
+                    "{} failed on a lock (attempt {} of {}), retrying in {} ms: {}",
+                    what,
+                    attempt,
+                    MAX_ATTEMPTS,
+                    delay.as_millis(),
+                    e
+                );
+                thread::sleep(delay);
+                RETRIES.fetch_add(1, Ordering::Relaxed);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The delay before the next attempt, with a random jitter of up to 50 %
+/// so that processes colliding once do not collide again.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    let jitter = RandomState::new().hash_one(attempt) % 1000;
+    delay + delay.mul_f64(jitter as f64 / 2000.0)
+}
+
+/// Exclusive lock on `<db file>.lock`, serializing schema changes of processes
+/// writing to the same database file. Released and removed when dropped.
+pub struct SchemaLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl SchemaLock {
+    /// Blocks until no other process holds the lock of the database file.
+    pub fn acquire(db_path: &str) -> Result<Self, TrnSysError> {
+        let path = PathBuf::from(format!("{}.lock", db_path));
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            debug!("Acquiring schema lock {}", path.display());
+            file.lock()?;
+            // the previous holder may have removed the file while waiting for it
+            if is_same_file(&file, &path) {
+                return Ok(SchemaLock { file, path });
+            }
+        }
+    }
+}
+
+impl Drop for SchemaLock {
+    fn drop(&mut self) {
+        // removed while still locked, so waiting processes notice and lock a new file;
+        // fails on Windows while other processes have the file open, the last one removes it
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether the open `file` is still the file at `path`.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Whether the open `file` is still the file at `path`.
+/// Windows does not remove files other processes have open.
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        for attempt in 1..MAX_ATTEMPTS {
+            let delay = backoff(attempt);
+            assert!(delay >= BASE_DELAY);
+            assert!(delay <= MAX_DELAY.mul_f64(1.5));
+        }
+        assert!(backoff(MAX_ATTEMPTS) >= MAX_DELAY);
+    }
+
+    #[test]
+    fn test_retry_gives_up_on_other_errors() {
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry("Test", || {
+            calls += 1;
+            Err(TrnSysError::GeneralError("failed".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_schema_lock_removed() {
+        let db_path = std::env::temp_dir().join("test_schema_lock.db");
+        let db_path = db_path.to_str().unwrap();
+        let lock_path = format!("{}.lock", db_path);
+        {
+            let _lock = SchemaLock::acquire(db_path).unwrap();
+            assert!(Path::new(&lock_path).exists());
+        }
+        assert!(!Path::new(&lock_path).exists());
+        // acquired again after the removal
+        drop(SchemaLock::acquire(db_path).unwrap());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::database::column::{key_col_names, MetaCol};
+use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::dialect::InsertMode;
+use crate::database::odbc::OdbcProvider;
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use indexmap::IndexSet;
+use tracing::info;
+
+/// Metadata table mapping the rows of each variant to the tables holding them.
+pub const SEGMENT_TABLE: &str = "RecorderSegments";
+
+/// Writes the rows of one variant into `Table`, rolling over to the continuation tables
+/// `Table_2`, `Table_3`, ... with the same columns once a table holds `max_rows` rows.
+///
+/// With a row limit, every table a variant is written to gets a row in [`SEGMENT_TABLE`]
+/// with its `SimTime` range, so readers can stitch the tables back together.
+pub struct TableRollover {
+    base_table: String,
+    variant_name: String,
+    cols: Vec<ColDef>,
+    max_rows: Option<u64>,
+    /// Columns getting a secondary index in every table, unless the primary key starts with them
+    indexes: Vec<String>,
+    insert_mode: InsertMode,
+    segment: u32,
+    /// Whether the current table has a row in the segment table yet
+    segment_recorded: bool,
+    /// Rows in the current table, counted on the first write to it and tracked from then on.
+    /// Replaced rows count as new ones, so the limit is never exceeded by this unit.
+    table_rows: Option<u64>,
+}
+
+impl TableRollover {
+    pub fn new(
+        base_table: &str,
+        variant_name: &str,
+        cols: Vec<ColDef>,
+        max_rows: Option<u64>,
+    ) -> Self {
+        TableRollover {
+            base_table: base_table.to_string(),
+            variant_name: variant_name.to_string(),
+            cols,
+            max_rows,
+            indexes: vec![],
+            insert_mode: InsertMode::default(),
+            segment: 1,
+            segment_recorded: false,
+            table_rows: None,
+        }
+    }
+
+    /// Creates an index on each of the columns in every table.
+    pub fn with_indexes(mut self, indexes: Vec<String>) -> Self {
+        self.indexes = indexes;
+        self
+    }
+
+    /// The name of the n-th table of `base_table`, counting from 1 for the base table itself.
+    pub fn segment_table_name(base_table: &str, segment: u32) -> String {
+        if segment <= 1 {
+            base_table.to_string()
+        } else {
+            format!("{}_{}", base_table, segment)
+        }
+    }
+
+    pub fn with_insert_mode(mut self, insert_mode: InsertMode) -> Self {
+        self.insert_mode = insert_mode;
+        self
+    }
+
+    /// The table the next rows are written to.
+    pub fn current_table(&self) -> String {
+        Self::segment_table_name(&self.base_table, self.segment)
+    }
+
+    /// Ensures the base table and removes earlier rows of the variant
+    /// from it, from all of its continuation tables and from the segment table.
+    ///
+    /// Returns the number of removed rows and the number of rows before the removal.
+    pub fn start(&mut self, provider: &dyn OdbcProvider<'_>) -> Result<(i64, i64), TrnSysError> {
+        if self.max_rows.is_some() {
+            provider.ensure_plain_table(SEGMENT_TABLE, segment_col_defs(), None)?;
+        }
+        if provider.has_table(SEGMENT_TABLE)? {
+            provider.delete_rows(SEGMENT_TABLE, self.segment_condition(provider, None))?;
+        }
+
+        self.open_segment(provider, 1)?;
+        let (mut removed_rows, mut total_rows) = (0, 0);
+        let mut segment = 1;
+        loop {
+            let table = Self::segment_table_name(&self.base_table, segment);
+            if segment > 1 && !provider.has_table(&table)? {
+                break;
+            }
+            total_rows += provider.count_rows(&table, None)?;
+            let variant_rows = provider
+                .count_rows(&table, Some(provider.variant_condition(&self.variant_name)))?;
+            if variant_rows > 0 {
+                provider.remove_variant(&table, &self.variant_name)?;
+            }
+            removed_rows += variant_rows;
+            segment += 1;
+        }
+        Ok((removed_rows, total_rows))
+    }
+
+    /// Writes the rows, filling up the current table and rolling over to the next ones.
+    pub fn write(
+        &mut self,
+        provider: &dyn OdbcProvider<'_>,
+        col_names: Vec<String>,
+        mut rows: Vec<Vec<DbValue>>,
+    ) -> Result<(), TrnSysError> {
+        let sim_time_index = col_names
+            .iter()
+            .position(|name| name == MetaCol::SimulationTime.as_str());
+        let sim_time = |row: &Vec<DbValue>| sim_time_index.and_then(|i| row[i].as_f64());
+
+        while !rows.is_empty() {
+            let table = self.current_table();
+            let free_rows = match self.max_rows {
+                Some(max_rows) => {
+                    let num_rows = match self.table_rows {
+                        Some(num_rows) => num_rows,
+                        None => provider.count_rows(&table, None)?.max(0) as u64,
+                    };
+                    self.table_rows = Some(num_rows);
+                    max_rows.saturating_sub(num_rows) as usize
+                }
+                None => rows.len(),
+            };
+            if free_rows == 0 {
+                self.open_segment(provider, self.segment + 1)?;
+                continue;
+            }
+
+            let rest = rows.split_off(free_rows.min(rows.len()));
+            let first_sim_time = rows.first().and_then(sim_time);
+            let last_sim_time = rows.last().and_then(sim_time);
+            let rows_written = rows.len();
+            provider.write_rows(
+                &table,
+                col_names.clone(),
+                rows,
+                self.insert_mode,
+                &key_col_names(&self.cols),
+            )?;
+            if self.max_rows.is_some() {
+                self.table_rows = self
+                    .table_rows
+                    .map(|num_rows| num_rows + rows_written as u64);
+                self.record_segment(provider, first_sim_time, last_sim_time)?;
+            }
+            rows = rest;
+        }
+        Ok(())
+    }
+
+    fn open_segment(
+        &mut self,
+        provider: &dyn OdbcProvider<'_>,
+        segment: u32,
+    ) -> Result<(), TrnSysError> {
+        self.segment = segment;
+        self.segment_recorded = false;
+        self.table_rows = None;
+        let table = self.current_table();
+        if segment > 1 {
+            info!(
+                "Row limit of {} reached, continuing in table {}",
+                self.base_table, table
+            );
+        }
+        provider.ensure_table(&table, self.cols.clone(), None)?;
+        let key_cols = key_col_names(&self.cols);
+        if self.insert_mode == InsertMode::Upsert {
+            provider.ensure_upsert_key(&table, &key_cols)?;
+        }
+        // the primary key serves as the index of its first column
+        let leading_key = key_cols.first().filter(|_| provider.support_primary_key());
+        let indexes = self
+            .indexes
+            .iter()
+            .filter(|name| Some(*name) != leading_key)
+            .cloned()
+            .collect::<Vec<_>>();
+        provider.ensure_indexes(&table, &indexes)
+    }
+
+    /// Adds the current table to the segment table, or extends its `SimTime` range.
+    fn record_segment(
+        &mut self,
+        provider: &dyn OdbcProvider<'_>,
+        first_sim_time: Option<f64>,
+        last_sim_time: Option<f64>,
+    ) -> Result<(), TrnSysError> {
+        let table = self.current_table();
+        let last_sim_time = last_sim_time.map(DbValue::Number).unwrap_or(DbValue::Null);
+        if self.segment_recorded {
+            provider.update_rows(
+                SEGMENT_TABLE,
+                vec![("LastSimTime".to_string(), last_sim_time)],
+                self.segment_condition(provider, Some(&table)),
+            )?;
+        } else {
+            provider.insert_data(
+                SEGMENT_TABLE,
+                vec![
+                    ("BaseTable".to_string(), self.base_table.as_str().into()),
+                    (
+                        MetaCol::Variant.as_str().to_string(),
+                        self.variant_name.as_str().into(),
+                    ),
+                    ("TableName".to_string(), table.into()),
+                    (
+                        "FirstSimTime".to_string(),
+                        first_sim_time.map(DbValue::Number).unwrap_or(DbValue::Null),
+                    ),
+                    ("LastSimTime".to_string(), last_sim_time),
+                ]
+                .into_iter()
+                .map(|(name, value)| (name, value.to_parameter()))
+                .collect(),
+            )?;
+            self.segment_recorded = true;
+        }
+        Ok(())
+    }
+
+    /// The condition selecting the rows of this variant in the segment table.
+    fn segment_condition(&self, provider: &dyn OdbcProvider<'_>, table: Option<&str>) -> String {
+        let mut condition = format!(
+            "{} = {} AND {} = {}",
+            provider.format_identifier("BaseTable"),
+            provider.format_text_literal(&self.base_table),
+            provider.format_identifier(MetaCol::Variant.as_str()),
+            provider.format_text_literal(&self.variant_name)
+        );
+        if let Some(table) = table {
+            condition.push_str(&format!(
+                " AND {} = {}",
+                provider.format_identifier("TableName"),
+                provider.format_text_literal(table)
+            ));
+        }
+        condition
+    }
+}
+
+/// The columns of the segment table.
+fn segment_col_defs() -> IndexSet<ColDef> {
+    IndexSet::from([
+        ColDef::new("BaseTable", ColDataType::Text, true, true),
+        MetaCol::Variant.col_def(),
+        ColDef::new("TableName", ColDataType::Text, true, true),
+        ColDef::new(
+            "FirstSimTime",
+            ColDataType::Number { decimal: true },
+            false,
+            false,
+        ),
+        ColDef::new(
+            "LastSimTime",
+            ColDataType::Number { decimal: true },
+            false,
+            false,
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_table_name() {
+        assert_eq!(TableRollover::segment_table_name("Result", 1), "Result");
+        assert_eq!(TableRollover::segment_table_name("Result", 2), "Result_2");
+        assert_eq!(TableRollover::segment_table_name("Result", 10), "Result_10");
+    }
+}
@@ -0,0 +1,203 @@
+use crate::trnsys::error::TrnSysError;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+
+/// Keys in a connection string whose values must never be logged.
+const SECRET_KEYS: [&str; 2] = ["PWD", "PASSWORD"];
+
+const REDACTED: &str = "***";
+
+/// A connection string (or file path) as given in the deck.
+///
+/// `Debug` and `Display` only show the redacted form,
+/// use [`ConnectionString::expose`] to get the raw value.
+#[derive(Clone, Default, PartialEq)]
+pub struct ConnectionString(String);
+
+impl ConnectionString {
+    pub fn new(value: &str) -> Self {
+        ConnectionString(value.to_string())
+    }
+
+    /// Returns the raw value, including placeholders and secrets.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for ConnectionString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", redact_secrets(&self.0))
+    }
+}
+
+impl Display for ConnectionString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", redact_secrets(&self.0))
+    }
+}
+
+/// A secret value such as a password. Never shown in `Debug` output.
+#[derive(Clone, Default, PartialEq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: &str) -> Self {
+        SecretString(value.to_string())
+    }
+
+    /// Returns the raw value, including placeholders.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", REDACTED)
+    }
+}
+
+/// Expands the placeholders in a connection string:
+///
+/// * `${NAME}` is replaced by the environment variable `NAME`.
+/// * `${file:path}` is replaced by the trimmed content of the file at `path`.
+/// * `$${` is an escaped, literal `${`.
+pub fn expand_placeholders(input: &str) -> Result<String, TrnSysError> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("$${") {
+            output.push_str("${");
+            rest = &rest[3..];
+        } else if rest.starts_with("${") {
+            let end = rest.find('}').ok_or(TrnSysError::ConversionError {
+                param: "Connection String".to_string(),
+                message: "Unclosed placeholder, expected '}'.".to_string(),
+            })?;
+            output.push_str(&resolve_placeholder(&rest[2..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+fn resolve_placeholder(placeholder: &str) -> Result<String, TrnSysError> {
+    if let Some(path) = placeholder.strip_prefix("file:") {
+        let content =
+            fs::read_to_string(path.trim()).map_err(|e| TrnSysError::ConversionError {
+                param: "Connection String".to_string(),
+                message: format!("Cannot read secret file '{}': {}", path.trim(), e),
+            })?;
+        Ok(content.trim().to_string())
+    } else {
+        std::env::var(placeholder.trim()).map_err(|e| TrnSysError::ConversionError {
+            param: "Connection String".to_string(),
+            message: format!("Cannot read environment variable '{}': {}", placeholder, e),
+        })
+    }
+}
+
+/// Replaces the values of all `PWD=...` and `Password=...` pairs with `***`.
+///
+/// Works on any text, so it can also be applied to error messages quoting a connection string.
+/// Values end at the next `;`, so they may contain spaces. Values wrapped in braces
+/// (`PWD={a;b}`) are redacted up to the closing brace, `}}` being an escaped brace.
+pub fn redact_secrets(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(eq) = rest.find('=') {
+        let key_start = rest[..eq]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let key = &rest[key_start..eq];
+        output.push_str(&rest[..=eq]);
+        rest = &rest[eq + 1..];
+
+        if SECRET_KEYS.iter().any(|k| k.eq_ignore_ascii_case(key)) {
+            let value_end = if rest.starts_with('{') {
+                closing_brace(rest).map(|i| i + 1).unwrap_or(rest.len())
+            } else {
+                rest.find(';').unwrap_or(rest.len())
+            };
+            output.push_str(REDACTED);
+            rest = &rest[value_end..];
+        }
+    }
+    output.push_str(rest);
+
+    output
+}
+
+/// The index of the brace closing the braced value at the start of `value`.
+fn closing_brace(value: &str) -> Option<usize> {
+    let mut chars = value.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '}' && chars.next_if(|(_, next)| *next == '}').is_none() {
+            return Some(i);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_env_placeholder() {
+        std::env::set_var("TRNSYS_ODBC_TEST_PWD", "s3cret");
+        let expanded =
+            expand_placeholders("Driver={PostgreSQL};UID=sim;PWD=${TRNSYS_ODBC_TEST_PWD};")
+                .unwrap();
+        assert_eq!(expanded, "Driver={PostgreSQL};UID=sim;PWD=s3cret;");
+    }
+
+    #[test]
+    fn test_expand_file_placeholder() {
+        let path = std::env::temp_dir().join("trnsys_odbc_secret_test.txt");
+        fs::write(&path, "from-file\n").unwrap();
+        let expanded =
+            expand_placeholders(&format!("PWD=${{file:{}}};", path.to_str().unwrap())).unwrap();
+        assert_eq!(expanded, "PWD=from-file;");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expand_escaped_and_missing() {
+        assert_eq!(expand_placeholders("a$${b}$c").unwrap(), "a${b}$c");
+        assert!(expand_placeholders("PWD=${TRNSYS_ODBC_SURELY_MISSING}").is_err());
+        assert!(expand_placeholders("PWD=${UNCLOSED").is_err());
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        assert_eq!(
+            redact_secrets("Driver={PostgreSQL};Server=db;UID=sim;PWD=s3cret;Port=5432"),
+            "Driver={PostgreSQL};Server=db;UID=sim;PWD=***;Port=5432"
+        );
+        assert_eq!(
+            redact_secrets("password={a;b}; pwd=x"),
+            "password=***; pwd=***"
+        );
+        assert_eq!(
+            redact_secrets("UID=sim;PWD=my pass;PWD={a}}b;c};Port=1"),
+            "UID=sim;PWD=***;PWD=***;Port=1"
+        );
+        assert_eq!(
+            redact_secrets("DBQ=C:\\sim\\result.accdb;"),
+            "DBQ=C:\\sim\\result.accdb;"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::database::column::{is_meta_col, MetaCol};
+use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::odbc::OdbcProvider;
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use indexmap::{IndexMap, IndexSet};
+use strum::IntoEnumIterator;
+use tracing::info;
+
+/// Metadata table mapping each column of a sharded table to the table holding it.
+pub const SHARD_TABLE: &str = "RecorderShards";
+
+/// The name of the n-th column shard of `base_table`, counting from 1 for the base table itself.
+///
+/// Shards are suffixed with `_Cols`, as `Table_2`, `Table_3`, ... are the row continuations.
+pub fn shard_table_name(base_table: &str, shard: u32) -> String {
+    if shard <= 1 {
+        base_table.to_string()
+    } else {
+        format!("{}_Cols{}", base_table, shard)
+    }
+}
+
+/// Splits the columns across `Table`, `Table_Cols2`, ... so that no table exceeds
+/// the column limit of the provider. Every shard also holds the meta columns
+/// and the other primary key columns, so the shards can be joined on their keys.
+///
+/// Columns already in one of the shards stay there, new columns go to the first shard
+/// with room left. Returns all existing shards and the new ones, starting with the base table.
+pub fn plan_shards(
+    provider: &dyn OdbcProvider<'_>,
+    base_table: &str,
+    cols: Vec<ColDef>,
+) -> Result<Vec<(String, Vec<ColDef>)>, TrnSysError> {
+    let Some(max_columns) = provider.get_capabilities().max_columns else {
+        return Ok(vec![(base_table.to_string(), cols)]);
+    };
+    // redefined meta columns and additional key columns go to every shard
+    let (shared, cols): (Vec<_>, Vec<_>) = cols
+        .into_iter()
+        .partition(|col| col.primary_key || is_meta_col(&col.name));
+    let num_shared = shared.iter().filter(|col| !is_meta_col(&col.name)).count();
+    let capacity = max_columns
+        .saturating_sub(MetaCol::iter().count() + num_shared)
+        .max(1);
+
+    // the columns of the existing shards
+    let mut existing: Vec<IndexSet<String>> = Vec::new();
+    loop {
+        let table = shard_table_name(base_table, existing.len() as u32 + 1);
+        if !provider.has_table(&table)? {
+            break;
+        }
+        let connection = provider.get_connection()?;
+        existing.push(
+            provider
+                .get_table_columns(&connection, &table)?
+                .into_iter()
+                .filter(|name| !is_meta_col(name) && shared.iter().all(|col| &col.name != name))
+                .collect(),
+        );
+    }
+
+    let mut shards: IndexMap<u32, Vec<ColDef>> = (1..=existing.len().max(1) as u32)
+        .map(|shard| (shard, shared.clone()))
+        .collect();
+    let mut num_cols = existing.iter().map(|cols| cols.len()).collect::<Vec<_>>();
+    for col in cols {
+        let shard = match existing.iter().position(|names| names.contains(&col.name)) {
+            Some(index) => index,
+            None => {
+                let index = num_cols
+                    .iter()
+                    .position(|n| *n < capacity)
+                    .unwrap_or(num_cols.len());
+                if index == num_cols.len() {
+                    num_cols.push(0);
+                }
+                num_cols[index] += 1;
+                index
+            }
+        };
+        shards
+            .entry(shard as u32 + 1)
+            .or_insert_with(|| shared.clone())
+            .push(col);
+    }
+    shards.sort_keys();
+
+    if shards.len() > 1 {
+        info!(
+            "{} is split into {} tables of at most {} columns.",
+            base_table,
+            shards.len(),
+            max_columns
+        );
+    }
+    Ok(shards
+        .into_iter()
+        .map(|(shard, cols)| (shard_table_name(base_table, shard), cols))
+        .collect())
+}
+
+/// Replaces the entries of `base_table` in [`SHARD_TABLE`] by the columns of the shards.
+/// Unsharded tables are not recorded.
+pub fn record_shards(
+    provider: &dyn OdbcProvider<'_>,
+    base_table: &str,
+    shards: &[(String, Vec<ColDef>)],
+) -> Result<(), TrnSysError> {
+    if shards.len() > 1 {
+        provider.ensure_plain_table(SHARD_TABLE, shard_col_defs(), None)?;
+    }
+    if !provider.has_table(SHARD_TABLE)? {
+        return Ok(());
+    }
+    provider.delete_rows(
+        SHARD_TABLE,
+        format!(
+            "{} = {}",
+            provider.format_identifier("BaseTable"),
+            provider.format_text_literal(base_table)
+        ),
+    )?;
+    if shards.len() <= 1 {
+        return Ok(());
+    }
+    for (table, cols) in shards {
+        // the columns in every shard are not recorded, as for the meta columns
+        for col in cols
+            .iter()
+            .filter(|col| !col.primary_key && !is_meta_col(&col.name))
+        {
+            provider.insert_data(
+                SHARD_TABLE,
+                vec![
+                    ("BaseTable".to_string(), DbValue::from(base_table)),
+                    ("ColumnName".to_string(), DbValue::from(col.name.as_str())),
+                    ("TableName".to_string(), DbValue::from(table.as_str())),
+                ]
+                .into_iter()
+                .map(|(name, value)| (name, value.to_parameter()))
+                .collect(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The columns of the shard table.
+fn shard_col_defs() -> IndexSet<ColDef> {
+    IndexSet::from([
+        ColDef::new("BaseTable", ColDataType::Text, true, true),
+        ColDef::new("ColumnName", ColDataType::Text, true, true),
+        ColDef::new("TableName", ColDataType::Text, true, false),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_table_name() {
+        assert_eq!(shard_table_name("Result", 1), "Result");
+        assert_eq!(shard_table_name("Result", 2), "Result_Cols2");
+    }
+}
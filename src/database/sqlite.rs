@@ -1,25 +1,144 @@
 use super::odbc::{FileDbProvider, OdbcProvider};
 use super::template::TemplateFile;
 use super::*;
-use crate::database::dialect::SqlDialect;
+use crate::database::dialect::{
+    on_conflict_upsert_query, text_list, ProviderCapabilities, SqlDialect,
+};
+use crate::database::registry::SharedConnection;
 use crate::impl_odbc_provider;
 use odbc_api::Connection;
-use std::sync::Mutex;
+use std::sync::Arc;
+use strum_macros::{Display, EnumString};
+use tracing::debug;
+
+/// `application_id` of the recorder files, "TRNS" in ASCII.
+pub const APPLICATION_ID: i32 = 0x54524E53;
+
+/// `user_version` of the recorder files, raised when the layout of the metadata tables changes.
+pub const USER_VERSION: i32 = 1;
+
+/// Page size of the template file.
+const TEMPLATE_PAGE_SIZE: u32 = 4096;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive, serialize_all = "UPPERCASE")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// Readers do not block the writer, see <https://www.sqlite.org/wal.html>.
+    #[default]
+    Wal,
+    Off,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive, serialize_all = "UPPERCASE")]
+pub enum Synchronous {
+    Off,
+    /// Safe in WAL mode, the last commits may be lost on a power failure.
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+/// PRAGMAs applied after connecting to an SQLite file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SqliteOptions {
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Page cache size, in pages if positive, in KiB if negative
+    pub cache_size: i64,
+    /// Page size of new files in bytes
+    pub page_size: u32,
+    /// Time in milliseconds to wait for a lock held by another connection
+    pub busy_timeout: u32,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            cache_size: -64_000,
+            page_size: TEMPLATE_PAGE_SIZE,
+            busy_timeout: 5_000,
+        }
+    }
+}
 
 pub struct SqliteProvider<'c> {
-    connection: Option<Mutex<Connection<'c>>>,
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
+    options: SqliteOptions,
+}
+
+impl SqliteProvider<'_> {
+    pub fn with_options(mut self, options: SqliteOptions) -> Self {
+        self.options = options;
+        self
+    }
 }
 
 impl SqlDialect for SqliteProvider<'_> {
+    fn get_rename_table_query(&self, from: &str, to: &str) -> Option<String> {
+        Some(format!("ALTER TABLE {} RENAME TO {}", from, to))
+    }
+
     fn get_integer_type(&self) -> String {
         "INTEGER".to_string()
     }
     fn get_decimal_type(&self) -> String {
         "REAL".to_string()
     }
+
+    fn default_capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            transactional_ddl: true,
+            max_columns: Some(2000),
+            // SQLITE_MAX_VARIABLE_NUMBER of versions before 3.32
+            max_params: Some(999),
+            ..Default::default()
+        }
+    }
+
+    fn get_compact_query(&self) -> Option<String> {
+        Some("VACUUM".to_string())
+    }
+
+    fn support_index_if_not_exists(&self) -> bool {
+        true
+    }
+
+    // the primary key is listed as the index sqlite_autoindex_<table>_1
+    fn get_unique_key_exists_query(&self, table_name: &str, key_cols: &[String]) -> Option<String> {
+        Some(format!(
+            "SELECT COUNT(*) FROM pragma_index_list({}) AS il WHERE il.\"unique\" = 1 \
+            AND (SELECT COUNT(*) FROM pragma_index_info(il.name)) = {} \
+            AND (SELECT COUNT(*) FROM pragma_index_info(il.name) WHERE name IN ({})) = {}",
+            self.format_text_literal(table_name),
+            key_cols.len(),
+            text_list(self, key_cols),
+            key_cols.len()
+        ))
+    }
+
+    fn get_upsert_query(
+        &self,
+        table_name: &str,
+        col_names: &[String],
+        key_cols: &[String],
+        num_rows: usize,
+    ) -> Option<String> {
+        Some(on_conflict_upsert_query(
+            self, table_name, col_names, key_cols, num_rows,
+        ))
+    }
 }
 
-impl_odbc_provider!(SqliteProvider);
+impl_odbc_provider!(SqliteProvider, options: SqliteOptions::default());
 
 impl<'c> FileDbProvider<'c> for SqliteProvider<'c> {
     fn get_template(&self) -> Result<TemplateFile, TrnSysError> {
@@ -29,4 +148,50 @@ impl<'c> FileDbProvider<'c> for SqliteProvider<'c> {
     fn get_driver_name(&self) -> String {
         "SQLite3 ODBC Driver".to_string()
     }
+
+    fn after_connect(&self, new_file: bool) -> Result<(), TrnSysError> {
+        let options = &self.options;
+        let mut pragmas = vec![];
+        if new_file {
+            pragmas.push(format!("PRAGMA application_id = {}", APPLICATION_ID));
+            pragmas.push(format!("PRAGMA user_version = {}", USER_VERSION));
+            // the page size can only be changed before switching to WAL
+            if options.page_size != TEMPLATE_PAGE_SIZE {
+                pragmas.push(format!("PRAGMA page_size = {}", options.page_size));
+                pragmas.push("VACUUM".to_string());
+            }
+        }
+        pragmas.extend([
+            format!("PRAGMA journal_mode = {}", options.journal_mode),
+            format!("PRAGMA synchronous = {}", options.synchronous),
+            format!("PRAGMA cache_size = {}", options.cache_size),
+            format!("PRAGMA busy_timeout = {}", options.busy_timeout),
+        ]);
+
+        let connection = self.get_connection()?;
+        for pragma in pragmas {
+            debug!("{}", pragma);
+            connection.execute(&pragma, ())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_header() {
+        let header = TemplateFile::Sqlite.to_binary();
+        assert!(header.starts_with(b"SQLite format 3\0"));
+        let read_i32 =
+            |offset: usize| i32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        assert_eq!(read_i32(60), USER_VERSION);
+        assert_eq!(read_i32(68), APPLICATION_ID);
+        assert_eq!(
+            u16::from_be_bytes([header[16], header[17]]) as u32,
+            TEMPLATE_PAGE_SIZE
+        );
+    }
 }
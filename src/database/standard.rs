@@ -0,0 +1,31 @@
+use super::odbc::OdbcProvider;
+use super::*;
+use crate::database::dialect::{ProviderCapabilities, SqlDialect};
+use crate::database::registry::SharedConnection;
+use crate::impl_odbc_provider;
+use odbc_api::Connection;
+use std::sync::Arc;
+
+/// Provider for databases without a dedicated dialect, using standard SQL.
+pub struct StandardSqlProvider<'c> {
+    connection: Option<Arc<SharedConnection<'c>>>,
+    capabilities: Option<ProviderCapabilities>,
+}
+
+impl SqlDialect for StandardSqlProvider<'_> {
+    // Standard SQL knows DOUBLE PRECISION, but not DOUBLE
+    fn get_decimal_type(&self) -> String {
+        "DOUBLE PRECISION".to_string()
+    }
+
+    fn get_datetime_type(&self) -> String {
+        "TIMESTAMP".to_string()
+    }
+
+    // Standard SQL uses double quotes for identifiers
+    fn format_identifier(&self, identifier: &str) -> String {
+        format!("\"{}\"", identifier.replace("\"", "\"\""))
+    }
+}
+
+impl_odbc_provider!(StandardSqlProvider);
@@ -1,6 +1,6 @@
 use crate::trnsys::error::TrnSysError;
 use include_dir::{include_dir, Dir};
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 
 static TEMPLATE_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/db_template");
 
@@ -14,14 +14,20 @@ impl TemplateFile {
     pub fn to_binary(&self) -> &'static [u8] {
         match self {
             TemplateFile::MsAccess => TEMPLATE_DIR.get_file("ms-access.accdb").unwrap().contents(),
-            TemplateFile::Sqlite => b"",
+            TemplateFile::Sqlite => TEMPLATE_DIR.get_file("sqlite.db").unwrap().contents(),
             TemplateFile::MsExcel => TEMPLATE_DIR.get_file("ms-excel.xlsx").unwrap().contents(),
         }
     }
 
-    pub fn create_file(&self, db_path: &str) -> Result<(), TrnSysError> {
-        let mut file = std::fs::File::create(db_path)?;
+    /// Creates the file from the template. Returns `false` if the file exists,
+    /// e.g. as another process created it in the meantime.
+    pub fn create_file(&self, db_path: &str) -> Result<bool, TrnSysError> {
+        let mut file = match std::fs::File::create_new(db_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
         file.write_all(self.to_binary())?;
-        Ok(())
+        Ok(true)
     }
 }
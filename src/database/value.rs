@@ -0,0 +1,83 @@
+use odbc_api::buffers::BufferDesc;
+use odbc_api::parameter::InputParameter;
+use odbc_api::IntoParameter;
+
+/// A single value to be written to the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbValue {
+    Null,
+    Number(f64),
+    Integer(i64),
+    Text(String),
+}
+
+impl DbValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            DbValue::Number(v) => Some(*v),
+            DbValue::Integer(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            DbValue::Integer(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            DbValue::Text(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Converts the value into a parameter for a single statement execution.
+    pub fn to_parameter(&self) -> Box<dyn InputParameter> {
+        match self {
+            DbValue::Null => Box::new(None::<f64>.into_parameter()),
+            DbValue::Number(v) => Box::new(v.into_parameter()),
+            DbValue::Integer(v) => Box::new(v.into_parameter()),
+            DbValue::Text(v) => Box::new(v.clone().into_parameter()),
+        }
+    }
+
+    /// The buffer needed to bind a column of these values as a parameter array.
+    /// `None` for `Null`, which fits into any buffer.
+    pub fn buffer_desc(&self) -> Option<BufferDesc> {
+        match self {
+            DbValue::Null => None,
+            DbValue::Number(_) => Some(BufferDesc::F64 { nullable: true }),
+            DbValue::Integer(_) => Some(BufferDesc::I64 { nullable: true }),
+            DbValue::Text(v) => Some(BufferDesc::Text {
+                max_str_len: v.len().max(1),
+            }),
+        }
+    }
+}
+
+impl From<f64> for DbValue {
+    fn from(value: f64) -> Self {
+        DbValue::Number(value)
+    }
+}
+
+impl From<i64> for DbValue {
+    fn from(value: i64) -> Self {
+        DbValue::Integer(value)
+    }
+}
+
+impl From<String> for DbValue {
+    fn from(value: String) -> Self {
+        DbValue::Text(value)
+    }
+}
+
+impl From<&str> for DbValue {
+    fn from(value: &str) -> Self {
+        DbValue::Text(value.to_string())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::database::column::{is_meta_col, MetaCol};
+use crate::database::datatype::ColDef;
+use crate::database::dialect::InsertMode;
+use crate::database::file_rollover::{record_split, remove_splits, FileRollover};
+use crate::database::odbc::OdbcProvider;
+use crate::database::path::clean_and_ensure_path;
+use crate::database::retry::{with_retry, SchemaLock};
+use crate::database::rollover::TableRollover;
+use crate::database::shard::{plan_shards, record_shards};
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use strum::IntoEnumIterator;
+use tracing::{info, warn};
+
+/// Destination of the rows recorded by one unit.
+pub trait RecordWriter: Send + Sync {
+    /// Prepares the table and removes earlier rows of the variant.
+    fn start(&mut self) -> Result<(), TrnSysError>;
+
+    fn write(&mut self, col_names: Vec<String>, rows: Vec<Vec<DbValue>>)
+        -> Result<(), TrnSysError>;
+
+    /// Called once after the last write of the simulation.
+    fn finish(&mut self) -> Result<(), TrnSysError> {
+        Ok(())
+    }
+}
+
+/// Minimum share of removed rows in a file that triggers a compaction.
+const COMPACT_MIN_SHARE: f64 = 0.2;
+
+/// The column names and the rollover of one column shard.
+type Shard = (Vec<String>, TableRollover);
+
+/// Writes the rows through an ODBC provider, splitting the columns across
+/// linked tables (see [`plan_shards`]) and rolling over to continuation tables,
+/// and for file databases to continuation files.
+pub struct OdbcWriter {
+    provider: Box<dyn OdbcProvider<'static>>,
+    table_name: String,
+    variant_name: String,
+    cols: Vec<ColDef>,
+    max_rows: Option<u64>,
+    indexes: Vec<String>,
+    insert_mode: InsertMode,
+    shards: Vec<Shard>,
+    file: Option<FileRollover>,
+    /// The database file whose schema lock is held while ensuring the tables
+    schema_lock_path: Option<String>,
+}
+
+impl OdbcWriter {
+    pub fn new(
+        provider: Box<dyn OdbcProvider<'static>>,
+        table_name: &str,
+        variant_name: &str,
+        cols: Vec<ColDef>,
+        max_rows: Option<u64>,
+    ) -> Self {
+        OdbcWriter {
+            provider,
+            table_name: table_name.to_string(),
+            variant_name: variant_name.to_string(),
+            cols,
+            max_rows,
+            indexes: vec![],
+            insert_mode: InsertMode::default(),
+            shards: vec![],
+            file: None,
+            schema_lock_path: None,
+        }
+    }
+
+    /// Creates an index on each of the columns, in the tables holding the column.
+    pub fn with_indexes(mut self, indexes: Vec<String>) -> Self {
+        self.indexes = indexes;
+        self
+    }
+
+    pub fn with_insert_mode(mut self, insert_mode: InsertMode) -> Self {
+        self.insert_mode = insert_mode;
+        self
+    }
+
+    /// Serializes the schema changes of all processes writing to the database file,
+    /// see [`SchemaLock`].
+    pub fn with_schema_lock(mut self, db_path: &str) -> Result<Self, TrnSysError> {
+        self.schema_lock_path = Some(clean_and_ensure_path(db_path)?);
+        Ok(self)
+    }
+
+    /// Continues in a new file once the database file is full.
+    pub fn with_file_rollover(mut self, file: FileRollover) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Ensures the tables in the database of the provider and removes earlier rows of the variant,
+    /// compacting the database if a large share of its rows was removed.
+    ///
+    /// Holds the schema lock of `db_path`, if given, until done.
+    fn open_tables(
+        &self,
+        provider: &dyn OdbcProvider<'_>,
+        db_path: Option<String>,
+    ) -> Result<Vec<Shard>, TrnSysError> {
+        let _lock = db_path.as_deref().map(SchemaLock::acquire).transpose()?;
+        let shards = plan_shards(provider, &self.table_name, self.cols.clone())?;
+        record_shards(provider, &self.table_name, &shards)?;
+
+        let mut shards = shards
+            .into_iter()
+            .map(|(table, cols)| {
+                let col_names: Vec<String> = MetaCol::iter()
+                    .map(|meta_col| meta_col.as_str().to_string())
+                    .chain(
+                        cols.iter()
+                            .filter(|col| !is_meta_col(&col.name))
+                            .map(|col| col.name.clone()),
+                    )
+                    .collect();
+                let indexes = self
+                    .indexes
+                    .iter()
+                    .filter(|name| col_names.contains(name))
+                    .cloned()
+                    .collect();
+                let rollover = TableRollover::new(&table, &self.variant_name, cols, self.max_rows)
+                    .with_indexes(indexes)
+                    .with_insert_mode(self.insert_mode);
+                (col_names, rollover)
+            })
+            .collect::<Vec<_>>();
+
+        let (mut removed_rows, mut total_rows) = (0, 0);
+        for (_, rollover) in shards.iter_mut() {
+            let (removed, total) = rollover.start(provider)?;
+            removed_rows += removed;
+            total_rows += total;
+        }
+        remove_splits(provider, &self.variant_name)?;
+        if removed_rows > 0 && removed_rows as f64 >= COMPACT_MIN_SHARE * total_rows as f64 {
+            // only frees space, so a database still busy after the retries is left as is
+            if let Err(e) = with_retry("Compacting the database", || provider.compact()) {
+                warn!("Compacting the database failed: {}", e);
+            }
+        }
+        Ok(shards)
+    }
+
+    /// The path of the schema lock of `db_path`, if schema changes are serialized.
+    fn lock_path(&self, db_path: String) -> Option<String> {
+        self.schema_lock_path.as_ref().map(|_| db_path)
+    }
+
+    fn file_is_full(&self) -> Result<bool, TrnSysError> {
+        match self.file.as_ref() {
+            Some(file) => file.is_full(),
+            None => Ok(false),
+        }
+    }
+
+    /// Records the split in the full file and continues in the next one.
+    fn advance_file(&mut self, first_sim_time: Option<f64>) -> Result<(), TrnSysError> {
+        let file = self.file.as_mut().unwrap();
+        let next_path = file.next_path();
+        info!(
+            "{} reached its size limit, continuing in {}",
+            file.current_path(),
+            next_path
+        );
+        record_split(
+            self.provider.as_ref(),
+            &self.variant_name,
+            &next_path,
+            first_sim_time,
+        )?;
+        self.provider = file.advance()?;
+        let current_path = file.current_path();
+        let lock_path = self.lock_path(current_path);
+        self.shards = self.open_tables(self.provider.as_ref(), lock_path)?;
+        Ok(())
+    }
+}
+
+impl RecordWriter for OdbcWriter {
+    fn start(&mut self) -> Result<(), TrnSysError> {
+        let lock_path = self.schema_lock_path.clone();
+        self.shards = self.open_tables(self.provider.as_ref(), lock_path)?;
+
+        // the rows of an earlier run may continue in other files
+        if let Some(file) = self.file.as_ref() {
+            for path in file.continuation_paths()? {
+                let provider = file.connect(&path)?;
+                self.open_tables(provider.as_ref(), self.lock_path(path))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        col_names: Vec<String>,
+        rows: Vec<Vec<DbValue>>,
+    ) -> Result<(), TrnSysError> {
+        while self.file_is_full()? {
+            let first_sim_time = col_names
+                .iter()
+                .position(|name| name == MetaCol::SimulationTime.as_str())
+                .and_then(|i| rows.first().and_then(|row| row[i].as_f64()));
+            self.advance_file(first_sim_time)?;
+        }
+
+        if let [(_, rollover)] = self.shards.as_mut_slice() {
+            return rollover.write(self.provider.as_ref(), col_names, rows);
+        }
+        for (shard_cols, rollover) in self.shards.iter_mut() {
+            let indices = shard_cols
+                .iter()
+                .filter_map(|name| col_names.iter().position(|n| n == name))
+                .collect::<Vec<_>>();
+            let shard_rows = rows
+                .iter()
+                .map(|row| indices.iter().map(|i| row[*i].clone()).collect())
+                .collect();
+            let shard_col_names = indices.iter().map(|i| col_names[*i].clone()).collect();
+            rollover.write(self.provider.as_ref(), shard_col_names, shard_rows)?;
+        }
+        Ok(())
+    }
+}
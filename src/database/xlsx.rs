@@ -0,0 +1,632 @@
+use crate::database::column::MetaCol;
+use crate::database::path::clean_and_ensure_path;
+use crate::database::retry::SchemaLock;
+use crate::database::rollover::{TableRollover, SEGMENT_TABLE};
+use crate::database::value::DbValue;
+use crate::database::writer::RecordWriter;
+use crate::trnsys::error::TrnSysError;
+use calamine::{open_workbook, Data, Reader, SheetType, Xlsx};
+use indexmap::IndexMap;
+use rust_xlsxwriter::{Format, Workbook};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info};
+
+/// Rows above the data in sheets written by the recorder: the column names and the units.
+const HEADER_ROWS: usize = 2;
+
+/// 1,048,576 rows per sheet, minus the header rows.
+pub const MAX_DATA_ROWS: u64 = 1_048_576 - HEADER_ROWS as u64;
+
+/// Unit of the `SimTime` column in the header
+const SIM_TIME_UNIT: &str = "h";
+
+/// Minimum wall-clock time between two saves of a workbook during the simulation.
+/// Each save rewrites the whole file, so saving after every flush would slow down large files.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The cells of one worksheet, row by row.
+struct Sheet {
+    rows: Vec<Vec<DbValue>>,
+    /// Number of header rows, shown in bold and frozen
+    header_rows: usize,
+    /// Number format of the data cells, `None` for `General`
+    number_format: Option<String>,
+}
+
+impl Sheet {
+    fn new(header_rows: usize) -> Self {
+        Sheet {
+            rows: vec![vec![]; header_rows],
+            header_rows,
+            number_format: None,
+        }
+    }
+
+    fn num_data_rows(&self) -> usize {
+        self.rows.len().saturating_sub(self.header_rows)
+    }
+
+    /// The index of the column, appended to the header if missing.
+    fn col_index(&mut self, name: &str, unit: &str) -> usize {
+        let header = &mut self.rows[0];
+        if let Some(index) = header.iter().position(|cell| cell.as_text() == Some(name)) {
+            return index;
+        }
+        header.push(name.into());
+        let index = header.len() - 1;
+        if self.header_rows > 1 {
+            let units = &mut self.rows[1];
+            units.resize(index, DbValue::Null);
+            units.push(if unit.is_empty() {
+                DbValue::Null
+            } else {
+                unit.into()
+            });
+        }
+        index
+    }
+
+    /// Removes the data rows for which the text in every given column equals the given value.
+    fn remove_rows(&mut self, filter: &[(&str, &str)]) {
+        let Some(header) = self.rows.first() else {
+            return;
+        };
+        let Some(filter) = filter
+            .iter()
+            .map(|(name, value)| {
+                header
+                    .iter()
+                    .position(|cell| cell.as_text() == Some(name))
+                    .map(|index| (index, *value))
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        let mut index = 0;
+        let header_rows = self.header_rows;
+        self.rows.retain(|row| {
+            index += 1;
+            index <= header_rows
+                || !filter.iter().all(|(col, value)| {
+                    row.get(*col).and_then(|cell| cell.as_text()) == Some(value)
+                })
+        });
+    }
+}
+
+/// A workbook held in memory, shared by all units writing to the same file.
+/// Existing sheets are loaded when opening and written back by [`SharedWorkbook::save`].
+///
+/// Only the cell values are kept, so only workbooks written by the recorder are loaded.
+/// Loading and saving hold the [`SchemaLock`] of the file. Other processes cannot share
+/// the workbook, as each save replaces the whole file; saving fails if another process
+/// changed the file since it was loaded.
+pub struct SharedWorkbook {
+    path: String,
+    sheets: Mutex<IndexMap<String, Sheet>>,
+    last_save: Mutex<Instant>,
+    /// Modification time of the file when loaded or last saved, `None` if there was no file
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl SharedWorkbook {
+    fn load(path: &str) -> Result<Self, TrnSysError> {
+        let _lock = SchemaLock::acquire(path)?;
+        let mut sheets = IndexMap::new();
+        if fs::exists(path)? {
+            info!("Loading workbook: {}", path);
+            let mut workbook: Xlsx<_> = open_workbook(path)?;
+            let not_recorded = |what: String| {
+                TrnSysError::SpreadsheetError(format!(
+                    "{} was not written by the recorder: {}. Saving it would lose everything but \
+                    the cell values, write the results to a new file instead.",
+                    path, what
+                ))
+            };
+            if let Some(sheet) = workbook
+                .sheets_metadata()
+                .iter()
+                .find(|sheet| sheet.typ != SheetType::WorkSheet)
+            {
+                return Err(not_recorded(format!("{} is a {:?}", sheet.name, sheet.typ)));
+            }
+            for name in workbook.sheet_names() {
+                let range = workbook.worksheet_range(&name)?;
+                let (start_row, start_col) = range.start().unwrap_or_default();
+                let mut rows = vec![vec![]; start_row as usize];
+                rows.extend(range.rows().map(|row| {
+                    std::iter::repeat_n(DbValue::Null, start_col as usize)
+                        .chain(row.iter().map(cell_value))
+                        .collect::<Vec<_>>()
+                }));
+                let header_rows = if name == SEGMENT_TABLE {
+                    1
+                } else if rows
+                    .first()
+                    .and_then(|row| row.first())
+                    .and_then(|c| c.as_text())
+                    == Some(MetaCol::Variant.as_str())
+                {
+                    HEADER_ROWS
+                } else {
+                    return Err(not_recorded(format!(
+                        "sheet {} has no recorder header",
+                        name
+                    )));
+                };
+                // the units row has no unit for the Variant column, a variant name is a data row
+                if header_rows == HEADER_ROWS
+                    && rows
+                        .get(1)
+                        .and_then(|row| row.first())
+                        .is_some_and(|cell| *cell != DbValue::Null)
+                {
+                    return Err(not_recorded(format!("sheet {} has no units row", name)));
+                }
+                // empty header rows at the end of the sheet are not read
+                if rows.len() < header_rows {
+                    rows.resize(header_rows, vec![]);
+                }
+                if workbook
+                    .worksheet_formula(&name)?
+                    .used_cells()
+                    .any(|(_, _, formula)| !formula.is_empty())
+                {
+                    return Err(not_recorded(format!("sheet {} has formulas", name)));
+                }
+                sheets.insert(
+                    name,
+                    Sheet {
+                        rows,
+                        header_rows,
+                        number_format: None,
+                    },
+                );
+            }
+        } else {
+            info!("Creating workbook: {}", path);
+        }
+        Ok(SharedWorkbook {
+            path: path.to_string(),
+            sheets: Mutex::new(sheets),
+            last_save: Mutex::new(Instant::now()),
+            modified: Mutex::new(modified_time(path)?),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, IndexMap<String, Sheet>>, TrnSysError> {
+        self.sheets
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))
+    }
+
+    /// Writes all sheets to the file, replacing it.
+    /// Fails if another process changed the file since it was loaded or last saved.
+    pub fn save(&self) -> Result<(), TrnSysError> {
+        let sheets = self.lock()?;
+        let _lock = SchemaLock::acquire(&self.path)?;
+        let mut modified = self
+            .modified
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))?;
+        if modified_time(&self.path)? != *modified {
+            return Err(TrnSysError::SpreadsheetError(format!(
+                "{} was changed by another process. Saving would lose its changes, \
+                write the results of parallel simulations to separate files instead.",
+                self.path
+            )));
+        }
+        let mut workbook = Workbook::new();
+        let header_format = Format::new().set_bold();
+
+        for (name, sheet) in sheets.iter() {
+            let worksheet = workbook.add_worksheet();
+            worksheet.set_name(name)?;
+            let number_format = sheet
+                .number_format
+                .as_ref()
+                .map(|format| Format::new().set_num_format(format));
+
+            for (r, row) in sheet.rows.iter().enumerate() {
+                let is_header = r < sheet.header_rows;
+                for (c, cell) in row.iter().enumerate() {
+                    let (r, c) = (r as u32, c as u16);
+                    match (cell, is_header, &number_format) {
+                        (DbValue::Null, _, _) => {}
+                        (DbValue::Text(text), true, _) => {
+                            worksheet.write_string_with_format(r, c, text, &header_format)?;
+                        }
+                        (DbValue::Text(text), false, _) => {
+                            worksheet.write_string(r, c, text)?;
+                        }
+                        (cell, false, Some(format)) => {
+                            let value = cell.as_f64().unwrap_or_default();
+                            worksheet.write_number_with_format(r, c, value, format)?;
+                        }
+                        (cell, _, _) => {
+                            worksheet.write_number(r, c, cell.as_f64().unwrap_or_default())?;
+                        }
+                    }
+                }
+            }
+
+            if sheet.header_rows > 0 {
+                // keep the header and the Variant and SimTime columns in view
+                let frozen_cols = if sheet.header_rows == HEADER_ROWS {
+                    2
+                } else {
+                    0
+                };
+                worksheet.set_freeze_panes(sheet.header_rows as u32, frozen_cols)?;
+            }
+        }
+
+        debug!("Saving workbook: {}", self.path);
+        workbook.save(&self.path)?;
+        *modified = modified_time(&self.path)?;
+        if let Ok(mut last_save) = self.last_save.lock() {
+            *last_save = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Saves the workbook if the last save is at least [`SAVE_INTERVAL`] ago,
+    /// so a simulation stopping early loses at most the rows of that interval.
+    pub fn save_if_due(&self) -> Result<(), TrnSysError> {
+        let due = self
+            .last_save
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))?
+            .elapsed()
+            >= SAVE_INTERVAL;
+        if due {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// The modification time of the file, `None` if it does not exist.
+fn modified_time(path: &str) -> Result<Option<SystemTime>, TrnSysError> {
+    match fs::exists(path)? {
+        true => Ok(Some(fs::metadata(path)?.modified()?)),
+        false => Ok(None),
+    }
+}
+
+fn cell_value(cell: &Data) -> DbValue {
+    match cell {
+        Data::Empty => DbValue::Null,
+        Data::Int(v) => DbValue::Integer(*v),
+        Data::Float(v) => DbValue::Number(*v),
+        Data::Bool(v) => DbValue::Number(if *v { 1. } else { 0. }),
+        Data::DateTime(v) => DbValue::Number(v.as_f64()),
+        Data::String(v) => DbValue::Text(v.clone()),
+        other => DbValue::Text(other.to_string()),
+    }
+}
+
+/// Process-wide registry of open workbooks, keyed by path.
+pub struct WorkbookRegistry {
+    workbooks: Mutex<HashMap<String, Weak<SharedWorkbook>>>,
+}
+
+impl WorkbookRegistry {
+    pub fn new() -> Self {
+        WorkbookRegistry {
+            workbooks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the open workbook at the path, or loads it if there is none.
+    pub fn open(&self, path: &str) -> Result<Arc<SharedWorkbook>, TrnSysError> {
+        let path = clean_and_ensure_path(path)?;
+        let mut workbooks = self
+            .workbooks
+            .lock()
+            .map_err(|_| TrnSysError::GeneralError("Mutex Lock Error".to_string()))?;
+        workbooks.retain(|_, workbook| workbook.strong_count() > 0);
+
+        if let Some(shared) = workbooks.get(&path).and_then(|w| w.upgrade()) {
+            return Ok(shared);
+        }
+        let shared = Arc::new(SharedWorkbook::load(&path)?);
+        workbooks.insert(path, Arc::downgrade(&shared));
+        Ok(shared)
+    }
+}
+
+/// Writes the rows of one variant into a sheet of an xlsx file, without an ODBC driver.
+///
+/// The sheet has the column names and the units as header rows. Like [`TableRollover`],
+/// rows beyond the row limit go to the sheets `Table_2`, `Table_3`, ...,
+/// recorded in the sheet [`SEGMENT_TABLE`].
+pub struct XlsxWriter {
+    workbook: Arc<SharedWorkbook>,
+    base_sheet: String,
+    variant_name: String,
+    /// The unit of each column, by column name
+    units: HashMap<String, String>,
+    number_format: Option<String>,
+    max_rows: u64,
+    segment: u32,
+}
+
+impl XlsxWriter {
+    pub fn new(
+        workbook: Arc<SharedWorkbook>,
+        base_sheet: &str,
+        variant_name: &str,
+        units: HashMap<String, String>,
+        number_format: Option<String>,
+        max_rows: Option<u64>,
+    ) -> Self {
+        XlsxWriter {
+            workbook,
+            base_sheet: base_sheet.to_string(),
+            variant_name: variant_name.to_string(),
+            units,
+            number_format,
+            max_rows: max_rows.unwrap_or(MAX_DATA_ROWS).min(MAX_DATA_ROWS),
+            segment: 1,
+        }
+    }
+
+    fn unit_of(&self, col_name: &str) -> &str {
+        if col_name == MetaCol::SimulationTime.as_str() {
+            return SIM_TIME_UNIT;
+        }
+        self.units
+            .get(col_name)
+            .map(|u| u.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Adds the sheet to the segment sheet, or extends its `SimTime` range.
+    fn record_segment(
+        &self,
+        sheets: &mut IndexMap<String, Sheet>,
+        sheet_name: &str,
+        first_sim_time: Option<f64>,
+        last_sim_time: Option<f64>,
+    ) {
+        let segments = sheets
+            .entry(SEGMENT_TABLE.to_string())
+            .or_insert_with(|| Sheet::new(1));
+        let key = [
+            ("BaseTable", self.base_sheet.as_str()),
+            (MetaCol::Variant.as_str(), self.variant_name.as_str()),
+            ("TableName", sheet_name),
+        ];
+        let indices = key
+            .iter()
+            .map(|(name, _)| segments.col_index(name, ""))
+            .collect::<Vec<_>>();
+        let first_index = segments.col_index("FirstSimTime", "");
+        let last_index = segments.col_index("LastSimTime", "");
+        let width = segments.rows[0].len();
+
+        let existing = segments.rows.iter_mut().skip(1).find(|row| {
+            indices
+                .iter()
+                .zip(key.iter())
+                .all(|(i, (_, value))| row.get(*i).and_then(|c| c.as_text()) == Some(value))
+        });
+        let row = match existing {
+            Some(row) => row,
+            None => {
+                let mut row = vec![DbValue::Null; width];
+                for (i, (_, value)) in indices.iter().zip(key.iter()) {
+                    row[*i] = (*value).into();
+                }
+                row[first_index] = first_sim_time.map(DbValue::Number).unwrap_or(DbValue::Null);
+                segments.rows.push(row);
+                segments.rows.last_mut().unwrap()
+            }
+        };
+        row.resize(width, DbValue::Null);
+        row[last_index] = last_sim_time.map(DbValue::Number).unwrap_or(DbValue::Null);
+    }
+}
+
+impl RecordWriter for XlsxWriter {
+    fn start(&mut self) -> Result<(), TrnSysError> {
+        let mut sheets = self.workbook.lock()?;
+        if let Some(segments) = sheets.get_mut(SEGMENT_TABLE) {
+            segments.remove_rows(&[
+                ("BaseTable", &self.base_sheet),
+                (MetaCol::Variant.as_str(), &self.variant_name),
+            ]);
+        }
+        let mut segment = 1;
+        while let Some(sheet) = sheets.get_mut(&TableRollover::segment_table_name(
+            &self.base_sheet,
+            segment,
+        )) {
+            sheet.remove_rows(&[(MetaCol::Variant.as_str(), &self.variant_name)]);
+            segment += 1;
+        }
+        self.segment = 1;
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        col_names: Vec<String>,
+        rows: Vec<Vec<DbValue>>,
+    ) -> Result<(), TrnSysError> {
+        let workbook = self.workbook.clone();
+        let mut sheets = workbook.lock()?;
+        let sim_time_index = col_names
+            .iter()
+            .position(|name| name == MetaCol::SimulationTime.as_str());
+        let sim_time = |row: &Vec<DbValue>| sim_time_index.and_then(|i| row[i].as_f64());
+        let mut rows = rows.into_iter().peekable();
+
+        while rows.peek().is_some() {
+            let sheet_name = TableRollover::segment_table_name(&self.base_sheet, self.segment);
+            let sheet = sheets
+                .entry(sheet_name.clone())
+                .or_insert_with(|| Sheet::new(HEADER_ROWS));
+            let free_rows = self.max_rows.saturating_sub(sheet.num_data_rows() as u64) as usize;
+            if free_rows == 0 {
+                self.segment += 1;
+                info!(
+                    "Row limit of {} reached, continuing in sheet {}",
+                    self.base_sheet,
+                    TableRollover::segment_table_name(&self.base_sheet, self.segment)
+                );
+                continue;
+            }
+
+            if sheet.header_rows == 0 {
+                return Err(TrnSysError::SpreadsheetError(format!(
+                    "Sheet {} was not written by the recorder.",
+                    sheet_name
+                )));
+            }
+            sheet.number_format = self.number_format.clone();
+            let indices = col_names
+                .iter()
+                .map(|name| sheet.col_index(name, self.unit_of(name)))
+                .collect::<Vec<_>>();
+            let width = sheet.rows[0].len();
+
+            let chunk = rows.by_ref().take(free_rows).collect::<Vec<_>>();
+            let first_sim_time = chunk.first().and_then(sim_time);
+            let last_sim_time = chunk.last().and_then(sim_time);
+            for row in chunk {
+                let mut cells = vec![DbValue::Null; width];
+                for (index, value) in indices.iter().zip(row) {
+                    cells[*index] = value;
+                }
+                sheet.rows.push(cells);
+            }
+            self.record_segment(&mut sheets, &sheet_name, first_sim_time, last_sim_time);
+        }
+        drop(sheets);
+        workbook.save_if_due()
+    }
+
+    fn finish(&mut self) -> Result<(), TrnSysError> {
+        self.workbook.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xlsx_writer() {
+        let path = clean_and_ensure_path("test_native.xlsx").unwrap();
+        if fs::metadata(&path).is_ok() {
+            fs::remove_file(&path).unwrap();
+        }
+        let registry = WorkbookRegistry::new();
+        let col_names = vec![
+            "Variant".to_string(),
+            "SimTime".to_string(),
+            "T".to_string(),
+        ];
+        let rows = |variant: &str| -> Vec<Vec<DbValue>> {
+            (0..5)
+                .map(|i| vec![variant.into(), (i as f64).into(), 20.5.into()])
+                .collect()
+        };
+        let units = HashMap::from([("T".to_string(), "C".to_string())]);
+
+        for variant in ["V1", "V2", "V1"] {
+            let workbook = registry.open(&path).unwrap();
+            let mut writer = XlsxWriter::new(
+                workbook,
+                "Result",
+                variant,
+                units.clone(),
+                Some("0.00".to_string()),
+                Some(3),
+            );
+            writer.start().unwrap();
+            writer.write(col_names.clone(), rows(variant)).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let workbook = SharedWorkbook::load(&path).unwrap();
+        let sheets = workbook.lock().unwrap();
+        assert_eq!(
+            sheets.keys().collect::<Vec<_>>(),
+            vec!["Result", SEGMENT_TABLE, "Result_2", "Result_3", "Result_4"]
+        );
+        // the rerun of V1 refills the rows it freed, V2 keeps its sheets
+        assert_eq!(sheets["Result"].num_data_rows(), 3);
+        assert_eq!(sheets["Result_2"].num_data_rows(), 3);
+        assert_eq!(sheets["Result_3"].num_data_rows(), 3);
+        assert_eq!(sheets["Result_4"].num_data_rows(), 1);
+        assert_eq!(sheets["Result"].rows[1][2], DbValue::Text("C".to_string()));
+        assert_eq!(sheets[SEGMENT_TABLE].num_data_rows(), 5);
+        drop(sheets);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_foreign_workbook() {
+        let path = clean_and_ensure_path("test_foreign.xlsx").unwrap();
+        let save = |add_sheet: &dyn Fn(&mut Workbook)| {
+            let mut workbook = Workbook::new();
+            let worksheet = workbook.add_worksheet().set_name("Result").unwrap();
+            worksheet.write_string(0, 0, "Variant").unwrap();
+            worksheet.write_string(0, 1, "SimTime").unwrap();
+            add_sheet(&mut workbook);
+            workbook.save(&path).unwrap();
+        };
+
+        // a sheet with the column names only, the units row is added
+        save(&|_| {});
+        let workbook = SharedWorkbook::load(&path).unwrap();
+        workbook
+            .lock()
+            .unwrap()
+            .get_mut("Result")
+            .unwrap()
+            .col_index("T", "C");
+        workbook.save().unwrap();
+        drop(workbook);
+        // data right below the column names
+        save(&|workbook| {
+            let result = workbook.worksheet_from_name("Result").unwrap();
+            result.write_string(1, 0, "V1").unwrap();
+        });
+        assert!(SharedWorkbook::load(&path).is_err());
+        // a sheet added by the user
+        save(&|workbook| {
+            let notes = workbook.add_worksheet().set_name("Notes").unwrap();
+            notes.write_string(0, 0, "Design").unwrap();
+        });
+        assert!(SharedWorkbook::load(&path).is_err());
+        // a formula in a recorded sheet
+        save(&|workbook| {
+            let result = workbook.worksheet_from_name("Result").unwrap();
+            result.write_formula(2, 2, "=SUM(B3:B10)").unwrap();
+        });
+        assert!(SharedWorkbook::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_workbook_changed_by_other_process() {
+        let path = clean_and_ensure_path("test_changed.xlsx").unwrap();
+        if fs::metadata(&path).is_ok() {
+            fs::remove_file(&path).unwrap();
+        }
+        let first = SharedWorkbook::load(&path).unwrap();
+        let second = SharedWorkbook::load(&path).unwrap();
+        first.save().unwrap();
+        assert!(second.save().is_err());
+        first.save().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use crate::database::value::DbValue;
+use crate::trnsys::error::TrnSysError;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use strum_macros::{Display, EnumString};
+
+/// What happens when rows cannot be written to the database, selectable by name, e.g. `OnError=Spill`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum ErrorPolicy {
+    /// Stops the simulation.
+    #[default]
+    Fatal,
+    /// Warns and keeps the rows buffered for the next flush.
+    Warn,
+    /// Warns and appends the rows to a CSV file next to the deck.
+    Spill,
+}
+
+/// CSV file receiving the rows that could not be written to a table.
+#[derive(Debug, Clone)]
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    /// The spill file `<table>_<variant>.csv` in `dir`.
+    pub fn new(dir: &Path, table_name: &str, variant_name: &str) -> Self {
+        let file_name = format!("{}_{}.csv", table_name, variant_name)
+            .chars()
+            .map(|c| match c {
+                c if c.is_alphanumeric() || "-_.".contains(c) => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        SpillFile {
+            path: dir.join(file_name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the rows, preceded by the header if the file is new.
+    pub fn append(&self, col_names: &[String], rows: &[Vec<DbValue>]) -> Result<(), TrnSysError> {
+        let is_new = self.path.metadata().map_or(true, |m| m.len() == 0);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            let header = col_names.iter().map(|name| csv_field(name));
+            writeln!(writer, "{}", header.collect::<Vec<_>>().join(","))?;
+        }
+        for row in rows {
+            let fields = row.iter().map(|value| match value {
+                DbValue::Null => String::new(),
+                DbValue::Number(v) => v.to_string(),
+                DbValue::Integer(v) => v.to_string(),
+                DbValue::Text(v) => csv_field(v),
+            });
+            writeln!(writer, "{}", fields.collect::<Vec<_>>().join(","))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The text quoted if it contains a separator, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_spill_file() {
+        let dir = std::env::temp_dir();
+        let spill = SpillFile::new(&dir, "Results", "Run 1/a");
+        assert!(spill.path().ends_with("Results_Run_1_a.csv"));
+        let _ = fs::remove_file(spill.path());
+
+        let col_names = vec!["Variant".to_string(), "A".to_string()];
+        let row = vec![DbValue::from("a,\"b\""), DbValue::Number(0.5)];
+        spill
+            .append(&col_names, std::slice::from_ref(&row))
+            .unwrap();
+        spill.append(&col_names, &[row]).unwrap();
+        assert_eq!(
+            fs::read_to_string(spill.path()).unwrap(),
+            "Variant,A\n\"a,\"\"b\"\"\",0.5\n\"a,\"\"b\"\"\",0.5\n"
+        );
+        fs::remove_file(spill.path()).unwrap();
+    }
+}
@@ -2,17 +2,22 @@ use crate::logging::init_tracing;
 use crate::trnsys::error::{TrnSysError, TrnSysErrorHandler};
 use crate::trnsys::logging::cleanup_tracing;
 use crate::trnsys_type::TrnSysType;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
-use std::sync::{Arc, LazyLock, RwLock};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
 use tracing::{debug, error};
 use trnsys::*;
 
+mod alignment;
 mod database;
+mod error_policy;
 mod parameter;
+mod report;
+mod status;
 mod storage;
 mod trnsys;
 mod trnsys_type;
+mod variant;
 
 include!(concat!(env!("OUT_DIR"), "/generated_entrance.rs"));
 
@@ -25,6 +30,9 @@ static TYPE_INSTANCE_DICT: LazyLock<RwLock<HashMap<i32, Arc<RwLock<TrnSysType>>>
         RwLock::new(HashMap::new())
     });
 
+/// Units that had their last call of the simulation.
+static ENDED_UNITS: LazyLock<Mutex<HashSet<i32>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
 fn get_current_state() -> Arc<RwLock<TrnSysState>> {
     let mut dict = (&TRNSYS_STATE_DICT).write().unwrap();
     let unit = get_current_unit();
@@ -53,9 +61,21 @@ pub extern "C" fn entrance() {
         Ok(_) => {}
         Err(e) => {
             e.handle_in_trnsys(state.deref_mut());
-            error!("{:#}", e);
+            error!(code = e.code(), reported = true, "{}", e.trnsys_message());
         }
     }
+    // after reporting any error of the last call, so the log file is kept,
+    // and only after the last unit, as all units share the log file
+    if is_last_call_of_simulation() && all_units_ended(get_current_unit()) {
+        cleanup_tracing();
+    }
+}
+
+/// Records the last call of the unit. Whether every unit of the type had its last call.
+fn all_units_ended(unit: i32) -> bool {
+    let mut ended = ENDED_UNITS.lock().unwrap();
+    ended.insert(unit);
+    ended.len() >= TYPE_INSTANCE_DICT.read().unwrap().len()
 }
 
 fn main(state: &mut TrnSysState, type_instance: &mut TrnSysType) -> Result<(), TrnSysError> {
@@ -92,7 +112,6 @@ fn main(state: &mut TrnSysState, type_instance: &mut TrnSysType) -> Result<(), T
 
     if is_last_call_of_simulation() {
         type_instance.simulation_ends(state)?;
-        cleanup_tracing();
         return Ok(());
     }
 
@@ -1,8 +1,18 @@
-use crate::database::column::MetaCol;
-use crate::database::odbc::OdbcProvider;
+use crate::alignment::Alignment;
+use crate::database::column::{MetaCol, STEP_COL};
+use crate::database::dialect::{InsertMode, SqlDialectKind};
+use crate::database::secret::{ConnectionString, SecretString};
+use crate::database::sqlite::SqliteOptions;
+use crate::error_policy::ErrorPolicy;
 use crate::trnsys::error::{InputError, TrnSysError};
-use crate::trnsys::TrnSysState;
+use crate::trnsys::{get_current_unit, get_deck_filename, TrnSysState};
+use crate::variant::{expand_variant, VariantContext};
+use indexmap::IndexMap;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::fmt::Display;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+use time::OffsetDateTime;
 use tracing::{debug, info};
 
 #[derive(Debug, PartialEq, Clone, Copy, TryFromPrimitive, IntoPrimitive)]
@@ -13,18 +23,155 @@ pub enum DriverMode {
     SqliteFile = 3,
     ConnectionString = 4,
     Postgres = 5,
+    Dsn = 6,
+    XlsxFile = 7,
+}
+
+/// Optional labels of the form `Key=Value`, given after the input names.
+/// Keys are case-insensitive.
+#[derive(Debug, Clone, Default)]
+pub struct LabelOptions {
+    options: IndexMap<String, String>,
+}
+
+impl LabelOptions {
+    pub fn parse(labels: &[String]) -> Result<Self, TrnSysError> {
+        let mut options = IndexMap::new();
+        for label in labels {
+            let (key, value) = label.split_once('=').ok_or(TrnSysError::ConversionError {
+                param: "Options".to_string(),
+                message: format!("Label '{}' must have the form Key=Value.", label),
+            })?;
+            options.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+        Ok(LabelOptions { options })
+    }
+
+    /// The options after the connection string, table, variant and input names.
+    pub fn after_inputs(labels: &[String], num_inputs: usize) -> Result<Self, TrnSysError> {
+        Self::parse(&labels[(3 + num_inputs).min(labels.len())..])
+    }
+
+    /// Sets the option, replacing a label with the same key.
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.options
+            .insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options.get(&key.to_lowercase()).map(|v| v.as_str())
+    }
+
+    /// Parses the option value, `None` if the option is not given.
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, TrnSysError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .parse::<T>()
+                .map(Some)
+                .map_err(|e| TrnSysError::ConversionError {
+                    param: key.to_string(),
+                    message: format!("'{}': {}", value, e),
+                }),
+        }
+    }
+
+    /// Splits a comma-separated option, `None` if the option is not given.
+    pub fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_string())
+                .collect()
+        })
+    }
+
+    /// Parses a boolean option. Accepts `true/false`, `yes/no`, `on/off` and `1/0`.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, TrnSysError> {
+        match self.get(key).map(|v| v.to_lowercase()) {
+            None => Ok(None),
+            Some(v) if ["true", "yes", "on", "1"].contains(&v.as_str()) => Ok(Some(true)),
+            Some(v) if ["false", "no", "off", "0"].contains(&v.as_str()) => Ok(Some(false)),
+            Some(v) => Err(TrnSysError::ConversionError {
+                param: key.to_string(),
+                message: format!("'{}' is not a boolean value.", v),
+            }),
+        }
+    }
+}
+
+/// Recording of every iteration into `<table>_iterations`, enabled with `RecordIterations=true`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IterationRecording {
+    /// Simulation time from which iterations are recorded
+    pub start: Option<f64>,
+    /// Simulation time up to which iterations are recorded
+    pub stop: Option<f64>,
+    /// Only timesteps with more iterations are recorded
+    pub min_iterations: u32,
+}
+
+impl IterationRecording {
+    /// Whether the iterations at the simulation time are recorded.
+    pub fn includes(&self, simulation_time: f64) -> bool {
+        self.start.is_none_or(|start| simulation_time >= start)
+            && self.stop.is_none_or(|stop| simulation_time <= stop)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Parameters {
     pub print_interval: f64,
     pub driver_mode: DriverMode,
-    pub num_inputs: i32,
-    pub connection_string: String,
+    /// The connection string, file path or DSN name, depending on the driver mode
+    pub connection_string: ConnectionString,
     pub table_name: String,
     pub input_names: Vec<String>,
     pub variant_name: String,
-    pub primary_keys: Vec<MetaCol>,
+    /// The SQL dialect for driver modes without a fixed dialect
+    pub dialect: SqlDialectKind,
+    /// User for DSN connections
+    pub user: String,
+    /// Password for DSN connections
+    pub password: SecretString,
+    /// Maximum number of rows per table before rolling over to a continuation table
+    pub max_rows: Option<u64>,
+    /// Maximum size of a database file in bytes before rolling over to a continuation file
+    pub max_file_size: Option<u64>,
+    /// Units of the inputs, shown in the header of xlsx files
+    pub input_units: Vec<String>,
+    /// Number format of the values in xlsx files, e.g. `0.000`
+    pub number_format: Option<String>,
+    /// PRAGMAs of SQLite files
+    pub sqlite: SqliteOptions,
+    /// Columns getting a secondary index
+    pub indexes: Vec<String>,
+    /// Whether rows with an existing primary key are replaced
+    pub insert_mode: InsertMode,
+    /// Overrides whether the driver gets the rows as parameter arrays
+    pub param_arrays: Option<bool>,
+    /// Recording of the iterations, if enabled
+    pub iterations: Option<IterationRecording>,
+    /// Whether rows are interpolated at the exact print times
+    pub alignment: Alignment,
+    /// Whether the record number `Step` replaces `SimTime` in the primary key
+    pub step_key: bool,
+    /// Inputs integrated in the Simulation Summary Report
+    pub report_integrals: Vec<String>,
+    /// Inputs with their minimum and maximum in the Simulation Summary Report
+    pub report_min_max: Vec<String>,
+    /// Whether the deck, listing and log file are stored in the database at the end
+    pub store_artifacts: bool,
+    /// Wall-clock time of the simulation start, identifying the run
+    pub run_start: OffsetDateTime,
+    /// What happens when rows cannot be written
+    pub on_error: ErrorPolicy,
 }
 
 impl TryFrom<&TrnSysState> for Parameters {
@@ -32,10 +179,10 @@ impl TryFrom<&TrnSysState> for Parameters {
 
     fn try_from(state: &TrnSysState) -> Result<Self, Self::Error> {
         let value = &state.params;
-        if value.len() != 3 {
+        if value.len() < 3 {
             return Err(InputError::BadParameter {
                 index: 0,
-                message: "Exact 3 parameters must be given.".to_string(),
+                message: "At least 3 parameters must be given.".to_string(),
             }
             .into());
         }
@@ -60,13 +207,24 @@ impl TryFrom<&TrnSysState> for Parameters {
             .into());
         }
 
-        let connection_string = state.labels[0].clone();
+        let connection_string = ConnectionString::new(&state.labels[0]);
 
         let table_name = state.labels[1].clone();
 
-        let variant_name = state.labels[2].clone();
+        let run_start = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let variant_name = expand_variant(
+            &state.labels[2],
+            &VariantContext {
+                deck: &get_deck_filename(),
+                unit: get_current_unit(),
+                // the optional parameters after the fixed ones, e.g. deck constants
+                params: &value[3..].iter().map(|v| v.value).collect::<Vec<_>>(),
+                now: run_start,
+            },
+        )?;
 
-        let input_names: Vec<String> = state.labels[3..].to_vec();
+        let input_end = (3 + num_inputs.max(0) as usize).min(state.labels.len());
+        let input_names: Vec<String> = state.labels[3..input_end].to_vec();
 
         debug!("input_names: {:?}", input_names);
 
@@ -77,15 +235,131 @@ impl TryFrom<&TrnSysState> for Parameters {
             }.into());
         }
 
+        let options = LabelOptions::after_inputs(&state.labels, input_names.len())?;
+
+        let max_rows: Option<u64> = options.get_parsed("MaxRows")?;
+        if max_rows == Some(0) {
+            return Err(TrnSysError::ConversionError {
+                param: "MaxRows".to_string(),
+                message: "The row limit must be greater than 0.".to_string(),
+            });
+        }
+
+        let input_units: Vec<String> = options
+            .get("Units")
+            .map(|units| units.split(',').map(|u| u.trim().to_string()).collect())
+            .unwrap_or_default();
+        if !input_units.is_empty() && input_units.len() != input_names.len() {
+            return Err(TrnSysError::ConversionError {
+                param: "Units".to_string(),
+                message: format!(
+                    "Expected {} comma-separated units, got {}.",
+                    input_names.len(),
+                    input_units.len()
+                ),
+            });
+        }
+
+        let step_key = options.get_bool("StepKey")?.unwrap_or(false);
+        if step_key && input_names.iter().any(|name| name == STEP_COL) {
+            return Err(TrnSysError::ConversionError {
+                param: "StepKey".to_string(),
+                message: "An input named 'Step' clashes with the step column.".to_string(),
+            });
+        }
+
+        let indexes = options
+            .get("Indexes")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                MetaCol::iter()
+                    .map(|meta_col| meta_col.as_str())
+                    .chain(step_key.then_some(STEP_COL))
+                    .chain(input_names.iter().map(|n| n.as_str()))
+                    .find(|col| col.eq_ignore_ascii_case(name))
+                    .map(|col| col.to_string())
+                    .ok_or(TrnSysError::ConversionError {
+                        param: "Indexes".to_string(),
+                        message: format!("'{}' is neither a meta column nor an input.", name),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let iterations = match options.get_bool("RecordIterations")?.unwrap_or(false) {
+            true => Some(IterationRecording {
+                start: options.get_parsed("IterationStart")?,
+                stop: options.get_parsed("IterationStop")?,
+                min_iterations: options.get_parsed("IterationThreshold")?.unwrap_or(0),
+            }),
+            false => None,
+        };
+        if iterations.is_some() && input_names.iter().any(|name| name == "Iteration") {
+            return Err(TrnSysError::ConversionError {
+                param: "RecordIterations".to_string(),
+                message: "An input named 'Iteration' clashes with the iteration column."
+                    .to_string(),
+            });
+        }
+
+        let default_sqlite = SqliteOptions::default();
+        let sqlite = SqliteOptions {
+            journal_mode: options
+                .get_parsed("JournalMode")?
+                .unwrap_or(default_sqlite.journal_mode),
+            synchronous: options
+                .get_parsed("Synchronous")?
+                .unwrap_or(default_sqlite.synchronous),
+            cache_size: options
+                .get_parsed("CacheSize")?
+                .unwrap_or(default_sqlite.cache_size),
+            page_size: options
+                .get_parsed("PageSize")?
+                .unwrap_or(default_sqlite.page_size),
+            busy_timeout: options
+                .get_parsed("BusyTimeout")?
+                .unwrap_or(default_sqlite.busy_timeout),
+        };
+        if !(512..=65536).contains(&sqlite.page_size) || !sqlite.page_size.is_power_of_two() {
+            return Err(TrnSysError::ConversionError {
+                param: "PageSize".to_string(),
+                message: "The page size must be a power of two between 512 and 65536.".to_string(),
+            });
+        }
+
+        let report_integrals = resolve_inputs(&options, "ReportIntegrals", &input_names)?;
+        let report_min_max = resolve_inputs(&options, "ReportMinMax", &input_names)?;
+
         let parameters = Parameters {
             print_interval,
             driver_mode,
-            num_inputs,
             connection_string,
             table_name,
             input_names,
             variant_name,
-            primary_keys: vec![MetaCol::SimulationTime, MetaCol::Variant],
+            dialect: options.get_parsed("Dialect")?.unwrap_or_default(),
+            user: options.get("User").unwrap_or_default().to_string(),
+            password: SecretString::new(options.get("Password").unwrap_or_default()),
+            max_rows,
+            max_file_size: options
+                .get_parsed::<u64>("MaxFileSizeMB")?
+                .map(|mb| mb * 1024 * 1024),
+            input_units,
+            number_format: options.get("NumberFormat").map(|f| f.to_string()),
+            sqlite,
+            indexes,
+            insert_mode: options.get_parsed("InsertMode")?.unwrap_or_default(),
+            param_arrays: options.get_bool("ParamArrays")?,
+            iterations,
+            alignment: options.get_parsed("Alignment")?.unwrap_or_default(),
+            step_key,
+            report_integrals,
+            report_min_max,
+            store_artifacts: options.get_bool("StoreArtifacts")?.unwrap_or(false),
+            run_start,
+            on_error: options.get_parsed("OnError")?.unwrap_or_default(),
         };
 
         info!("Parameters: {:?}", parameters);
@@ -93,3 +367,26 @@ impl TryFrom<&TrnSysState> for Parameters {
         Ok(parameters)
     }
 }
+
+/// The inputs listed in a comma-separated option, matched case-insensitively.
+fn resolve_inputs(
+    options: &LabelOptions,
+    key: &str,
+    input_names: &[String],
+) -> Result<Vec<String>, TrnSysError> {
+    options
+        .get_list(key)
+        .unwrap_or_default()
+        .iter()
+        .map(|name| {
+            input_names
+                .iter()
+                .find(|input| input.eq_ignore_ascii_case(name))
+                .cloned()
+                .ok_or(TrnSysError::ConversionError {
+                    param: key.to_string(),
+                    message: format!("'{}' is not an input.", name),
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+use crate::parameter::{LabelOptions, Parameters};
+use crate::trnsys::{
+    init_report_integral, init_report_min_max, init_report_text, init_report_value,
+    set_number_of_report_variables, update_report_integral, update_report_min_max,
+};
+
+/// Text entries: the database target, the table and the variant.
+const NUM_TEXTS: i32 = 3;
+
+/// Value entries: the rows written.
+const NUM_VALUES: i32 = 1;
+
+/// Entries of the recorder in the Simulation Summary Report (SSR):
+/// where the results went, how many rows were written, and the integrals and
+/// minimum/maximum of the inputs selected with `ReportIntegrals` and `ReportMinMax`.
+pub struct SummaryReport {
+    /// The input indices of the integrated inputs
+    integrals: Vec<usize>,
+    /// The input indices of the inputs with minimum and maximum
+    min_max: Vec<usize>,
+}
+
+impl SummaryReport {
+    /// Declares the number of entries. Called at the very first call of the simulation,
+    /// when only the labels are known.
+    pub fn declare(options: &LabelOptions) {
+        let count = |key: &str| options.get_list(key).unwrap_or_default().len() as i32;
+        set_number_of_report_variables(
+            count("ReportIntegrals"),
+            count("ReportMinMax"),
+            NUM_VALUES,
+            NUM_TEXTS,
+        );
+    }
+
+    /// Initializes the entries at the simulation start.
+    pub fn init(params: &Parameters) -> Self {
+        let input_index = |name: &String| {
+            params
+                .input_names
+                .iter()
+                .position(|input| input == name)
+                .unwrap()
+        };
+        let unit = |i: usize| {
+            params
+                .input_units
+                .get(i)
+                .map(|unit| unit.as_str())
+                .unwrap_or("-")
+        };
+
+        let target = format!("{:?} {}", params.driver_mode, params.connection_string);
+        for (index, (name, value)) in [
+            ("Database", target.as_str()),
+            ("Table", params.table_name.as_str()),
+            ("Variant", params.variant_name.as_str()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            init_report_text(&mut (index as i32 + 1), name, value);
+        }
+        init_report_value(&mut 1, "Rows written", &0.0, "-");
+
+        let integrals = params
+            .report_integrals
+            .iter()
+            .map(input_index)
+            .collect::<Vec<_>>();
+        for (index, &i) in integrals.iter().enumerate() {
+            init_report_integral(
+                &mut (index as i32 + 1),
+                &params.input_names[i],
+                unit(i),
+                &integral_unit(unit(i)),
+            );
+        }
+        let min_max = params
+            .report_min_max
+            .iter()
+            .map(input_index)
+            .collect::<Vec<_>>();
+        for (index, &i) in min_max.iter().enumerate() {
+            init_report_min_max(&mut (index as i32 + 1), &params.input_names[i], unit(i));
+        }
+        SummaryReport { integrals, min_max }
+    }
+
+    /// Adds the converged inputs of a timestep.
+    pub fn update(&self, inputs: &[f64]) {
+        for (index, &i) in self.integrals.iter().enumerate() {
+            update_report_integral(&mut (index as i32 + 1), &mut inputs[i].clone());
+        }
+        for (index, &i) in self.min_max.iter().enumerate() {
+            update_report_min_max(&mut (index as i32 + 1), &mut inputs[i].clone());
+        }
+    }
+
+    /// Reports the number of rows written so far, after each flush. The SSR has no update
+    /// call for values, initializing the entry again replaces its value.
+    pub fn update_rows_written(&self, rows_written: u64) {
+        init_report_value(&mut 1, "Rows written", &(rows_written as f64), "-");
+    }
+}
+
+/// The unit of the integral over time in hours of a rate given in `unit`,
+/// e.g. `kJ` for `kJ/h`.
+fn integral_unit(unit: &str) -> String {
+    ["/hr", "/h"]
+        .iter()
+        .find_map(|suffix| unit.strip_suffix(suffix))
+        .map(|unit| unit.to_string())
+        .unwrap_or_else(|| match unit {
+            "-" => "h".to_string(),
+            unit => format!("{}*h", unit),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integral_unit() {
+        assert_eq!(integral_unit("kJ/h"), "kJ");
+        assert_eq!(integral_unit("kJ/hr"), "kJ");
+        assert_eq!(integral_unit("kW"), "kW*h");
+        assert_eq!(integral_unit("-"), "h");
+    }
+}
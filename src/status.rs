@@ -0,0 +1,92 @@
+use std::time::Duration;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// The outputs of the recorder, in the order of the output numbers.
+/// A deck declaring fewer outputs gets the first ones.
+#[derive(Debug, PartialEq, Clone, Copy, EnumIter)]
+pub enum StatusOutput {
+    /// Rows written to the database since the simulation start
+    RowsWritten,
+    /// Rows waiting in the buffer for the next flush
+    RowsBuffered,
+    /// Duration of the last flush in hours
+    LastFlushDuration,
+    /// Time spent in the database since the simulation start in hours
+    DatabaseTime,
+    /// 0 if fine, 1 if the last flush had to wait for a lock, 2 if it failed
+    ErrorFlag,
+}
+
+impl StatusOutput {
+    /// The TRNSYS unit code of the output.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            StatusOutput::LastFlushDuration | StatusOutput::DatabaseTime => "TM1",
+            _ => "DM1",
+        }
+    }
+}
+
+/// Health of the recorder, shown on its outputs.
+#[derive(Debug, Clone, Default)]
+pub struct RecorderStatus {
+    pub rows_written: u64,
+    pub rows_buffered: usize,
+    pub last_flush: Duration,
+    pub database_time: Duration,
+    /// Whether the last flush had to wait for a lock held by another connection
+    pub lagging: bool,
+    /// Whether the last flush failed
+    pub failed: bool,
+}
+
+impl RecorderStatus {
+    /// Accounts for a flush of `rows` rows, whether it succeeded or not.
+    pub fn record_flush(&mut self, rows: usize, duration: Duration, retried: bool, ok: bool) {
+        if ok {
+            self.rows_written += rows as u64;
+        }
+        self.last_flush = duration;
+        self.database_time += duration;
+        self.lagging = retried;
+        self.failed = !ok;
+    }
+
+    pub fn value(&self, output: StatusOutput) -> f64 {
+        match output {
+            StatusOutput::RowsWritten => self.rows_written as f64,
+            StatusOutput::RowsBuffered => self.rows_buffered as f64,
+            StatusOutput::LastFlushDuration => self.last_flush.as_secs_f64() / 3600.0,
+            StatusOutput::DatabaseTime => self.database_time.as_secs_f64() / 3600.0,
+            StatusOutput::ErrorFlag => match (self.failed, self.lagging) {
+                (true, _) => 2.0,
+                (false, true) => 1.0,
+                (false, false) => 0.0,
+            },
+        }
+    }
+
+    /// The values of the first `num_outputs` outputs.
+    pub fn values(&self, num_outputs: usize) -> Vec<f64> {
+        StatusOutput::iter()
+            .take(num_outputs)
+            .map(|output| self.value(output))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_values() {
+        let mut status = RecorderStatus::default();
+        status.record_flush(10, Duration::from_secs(36), true, true);
+        status.record_flush(5, Duration::from_secs(36), false, false);
+        status.rows_buffered = 5;
+        assert_eq!(status.values(2), vec![10.0, 5.0]);
+        assert_eq!(status.values(10), vec![10.0, 5.0, 0.01, 0.02, 2.0]);
+    }
+}
@@ -1,4 +1,10 @@
-use crate::trnsys::{found_bad_input, found_bad_parameter, Severity, TrnSysState};
+use crate::database::secret::redact_secrets;
+use crate::error_policy::ErrorPolicy;
+use crate::trnsys::{
+    found_bad_input, found_bad_parameter, is_last_call_of_simulation, log_message, Severity,
+    TrnSysState,
+};
+use odbc_api::handles::Record;
 use thiserror::Error;
 use tracing::error;
 
@@ -24,6 +30,97 @@ pub enum TrnSysError {
         #[from]
         source: std::io::Error,
     },
+    #[error("Spreadsheet Error: {0}")]
+    SpreadsheetError(String),
+}
+
+impl From<rust_xlsxwriter::XlsxError> for TrnSysError {
+    fn from(e: rust_xlsxwriter::XlsxError) -> Self {
+        TrnSysError::SpreadsheetError(e.to_string())
+    }
+}
+
+impl From<calamine::XlsxError> for TrnSysError {
+    fn from(e: calamine::XlsxError) -> Self {
+        TrnSysError::SpreadsheetError(e.to_string())
+    }
+}
+
+impl TrnSysError {
+    /// The error code reported to TRNSYS, as listed in the readme.
+    /// The codes are above 1000, so they are not mistaken for codes of the TRNSYS kernel.
+    pub fn code(&self) -> i32 {
+        match self {
+            TrnSysError::GeneralError(_) => 1001,
+            TrnSysError::InputError(_) => 1002,
+            TrnSysError::ConversionError { .. } => 1003,
+            TrnSysError::OdbcError { .. } => 1004,
+            TrnSysError::FileSystemError { .. } => 1005,
+            TrnSysError::SpreadsheetError(_) => 1006,
+        }
+    }
+
+    /// The severity of the error under the error policy of the unit.
+    /// Configuration errors and failed connections stop the simulation whatever the policy,
+    /// as no later call can succeed. Errors writing rows, including statements that were
+    /// still busy after all retries, are warnings when the policy keeps or spills the rows.
+    /// Fatal errors stop the simulation at the end of the timestep, after the last call
+    /// has flushed what can still be written. At the last call there is no timestep left,
+    /// so the error stops the simulation right away instead of letting it report completion.
+    pub(crate) fn severity(&self, policy: ErrorPolicy, last_call: bool) -> Severity {
+        let fatal = match last_call {
+            true => Severity::Stop,
+            false => Severity::Fatal,
+        };
+        match self {
+            TrnSysError::GeneralError(_)
+            | TrnSysError::InputError(_)
+            | TrnSysError::ConversionError { .. } => fatal,
+            TrnSysError::OdbcError { .. } if self.is_connection_error() => fatal,
+            TrnSysError::OdbcError { .. }
+            | TrnSysError::FileSystemError { .. }
+            | TrnSysError::SpreadsheetError(_) => match policy {
+                ErrorPolicy::Fatal => fatal,
+                // Rows kept in the buffer are lost if there is no later flush.
+                ErrorPolicy::Warn if last_call => fatal,
+                ErrorPolicy::Warn | ErrorPolicy::Spill => Severity::Warning,
+            },
+        }
+    }
+
+    /// Whether the connection to the data source could not be established,
+    /// e.g. because of a wrong connection string or rejected credentials.
+    fn is_connection_error(&self) -> bool {
+        self.odbc_record().is_some_and(|record| {
+            let state = record.state.as_str();
+            ["08001", "08004"].contains(&state) || state.starts_with("28")
+        })
+    }
+
+    /// The diagnostic record of an ODBC error.
+    pub fn odbc_record(&self) -> Option<&Record> {
+        match self {
+            TrnSysError::OdbcError {
+                source: odbc_api::Error::Diagnostics { record, .. },
+            } => Some(record),
+            _ => None,
+        }
+    }
+
+    /// The message reported to TRNSYS, with secrets redacted,
+    /// and the SQLSTATE and native error of ODBC errors.
+    pub fn trnsys_message(&self) -> String {
+        let message = redact_secrets(&format!("{:#}", self));
+        match self.odbc_record() {
+            Some(record) => format!(
+                "{} (SQLSTATE {}, native error {})",
+                message,
+                record.state.as_str(),
+                record.native_error
+            ),
+            None => message,
+        }
+    }
 }
 
 impl TrnSysErrorHandler for TrnSysError {
@@ -32,7 +129,13 @@ impl TrnSysErrorHandler for TrnSysError {
             TrnSysError::InputError(e) => {
                 e.handle_in_trnsys(state);
             }
-            _ => {}
+            // The error policy has already kept or spilled the rows it could,
+            // so an error reaching TRNSYS stops the simulation.
+            _ => log_message(
+                self.severity(ErrorPolicy::Fatal, is_last_call_of_simulation()),
+                self.code(),
+                &self.trnsys_message(),
+            ),
         }
     }
 }
@@ -58,3 +161,105 @@ impl TrnSysErrorHandler for InputError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use odbc_api::handles::State;
+
+    #[test]
+    fn test_error_codes() {
+        let odbc_error = TrnSysError::OdbcError {
+            source: odbc_api::Error::Diagnostics {
+                record: Record {
+                    state: State(*b"08001"),
+                    native_error: 17,
+                    message: vec![],
+                },
+                function: "SQLDriverConnect",
+            },
+        };
+        assert_eq!(odbc_error.code(), 1004);
+        assert!(odbc_error
+            .trnsys_message()
+            .ends_with("(SQLSTATE 08001, native error 17)"));
+
+        let general_error = TrnSysError::GeneralError("failed".to_string());
+        assert_eq!(general_error.code(), 1001);
+        assert_eq!(
+            general_error.trnsys_message(),
+            "Error in TrnSys Type: failed"
+        );
+    }
+
+    fn odbc_error(state: &[u8; 5]) -> TrnSysError {
+        TrnSysError::OdbcError {
+            source: odbc_api::Error::Diagnostics {
+                record: Record {
+                    state: State(*state),
+                    native_error: 0,
+                    message: b"database is locked".to_vec(),
+                },
+                function: "SQLExecute",
+            },
+        }
+    }
+
+    #[test]
+    fn test_config_error_severity() {
+        let errors = [
+            TrnSysError::GeneralError("failed".to_string()),
+            TrnSysError::ConversionError {
+                param: "Variant".to_string(),
+                message: "unknown".to_string(),
+            },
+            TrnSysError::InputError(InputError::BadParameter {
+                index: 1,
+                message: "bad".to_string(),
+            }),
+        ];
+        for error in errors {
+            for policy in [ErrorPolicy::Fatal, ErrorPolicy::Warn, ErrorPolicy::Spill] {
+                assert_eq!(error.severity(policy, false), Severity::Fatal);
+                assert_eq!(error.severity(policy, true), Severity::Stop);
+            }
+        }
+    }
+
+    #[test]
+    fn test_connection_error_severity() {
+        for state in [b"08001", b"08004", b"28000"] {
+            let error = odbc_error(state);
+            assert_eq!(error.severity(ErrorPolicy::Warn, false), Severity::Fatal);
+            assert_eq!(error.severity(ErrorPolicy::Spill, true), Severity::Stop);
+        }
+    }
+
+    #[test]
+    fn test_write_error_severity() {
+        let errors = [
+            odbc_error(b"42000"),
+            TrnSysError::FileSystemError {
+                source: std::io::Error::other("disk full"),
+            },
+            TrnSysError::SpreadsheetError("failed".to_string()),
+        ];
+        for error in errors {
+            assert_eq!(error.severity(ErrorPolicy::Fatal, false), Severity::Fatal);
+            assert_eq!(error.severity(ErrorPolicy::Fatal, true), Severity::Stop);
+            assert_eq!(error.severity(ErrorPolicy::Warn, false), Severity::Warning);
+            assert_eq!(error.severity(ErrorPolicy::Warn, true), Severity::Stop);
+            assert_eq!(error.severity(ErrorPolicy::Spill, false), Severity::Warning);
+            assert_eq!(error.severity(ErrorPolicy::Spill, true), Severity::Warning);
+        }
+    }
+
+    #[test]
+    fn test_busy_error_severity() {
+        let error = odbc_error(b"HY000");
+        assert!(crate::database::retry::is_busy(&error));
+        assert_eq!(error.severity(ErrorPolicy::Fatal, false), Severity::Fatal);
+        assert_eq!(error.severity(ErrorPolicy::Warn, false), Severity::Warning);
+        assert_eq!(error.severity(ErrorPolicy::Spill, true), Severity::Warning);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::trnsys::{
+    get_current_type, get_current_unit, get_simulation_time, get_timestep_iteration,
+};
+use serde_json::{Map, Value};
+use std::fmt;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Where in the simulation an event happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallInfo {
+    pub unit: i32,
+    pub type_no: i32,
+    pub simulation_time: f64,
+    pub iteration: i32,
+}
+
+impl CallInfo {
+    /// The call of the unit currently running.
+    pub fn current() -> Self {
+        CallInfo {
+            unit: get_current_unit(),
+            type_no: get_current_type(),
+            simulation_time: get_simulation_time(),
+            iteration: get_timestep_iteration(),
+        }
+    }
+}
+
+/// Collects the fields of an event or span as JSON values.
+#[derive(Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// The fields of a span, kept in its extensions by [`SpanFieldsLayer`].
+struct SpanFields(Map<String, Value>);
+
+/// Keeps the fields of each span as JSON values, for the span context of [`JsonFmt`].
+pub struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = JsonVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.0));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = JsonVisitor(std::mem::take(fields));
+            values.record(&mut visitor);
+            *fields = visitor.0;
+        }
+    }
+}
+
+/// Formats each event as a line of JSON, with the fields of the event, the unit, type,
+/// simulation time and timestep iteration of the call, and the spans from the outermost.
+pub struct JsonFmt {
+    call_info: fn() -> CallInfo,
+}
+
+impl JsonFmt {
+    /// Takes the call of each event from `call_info`, usually [`CallInfo::current`].
+    pub fn new(call_info: fn() -> CallInfo) -> Self {
+        JsonFmt { call_info }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut fields = JsonVisitor::default();
+        event.record(&mut fields);
+        let spans = ctx
+            .event_scope()
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| {
+                        let mut object = Map::new();
+                        object.insert("name".to_string(), span.name().into());
+                        if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                            object.extend(fields.clone());
+                        }
+                        Value::Object(object)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let call = (self.call_info)();
+        let timestamp = OffsetDateTime::now_local()
+            .unwrap_or_else(|_| OffsetDateTime::now_utc())
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), timestamp.into());
+        line.insert("level".to_string(), metadata.level().as_str().into());
+        line.insert("target".to_string(), metadata.target().into());
+        line.insert("filename".to_string(), metadata.file().into());
+        line.insert("line_number".to_string(), metadata.line().into());
+        line.insert("unit".to_string(), call.unit.into());
+        line.insert("type".to_string(), call.type_no.into());
+        line.insert("simulation_time".to_string(), call.simulation_time.into());
+        line.insert("iteration".to_string(), call.iteration.into());
+        line.insert("fields".to_string(), Value::Object(fields.0));
+        line.insert("spans".to_string(), Value::Array(spans));
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::info;
+    use tracing_subscriber::fmt;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::registry::Registry;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = Registry::default().with(SpanFieldsLayer).with(
+            fmt::layer()
+                .with_writer(move || writer.clone())
+                .event_format(JsonFmt::new(|| CallInfo {
+                    unit: 12,
+                    type_no: 201,
+                    simulation_time: 1.5,
+                    iteration: 3,
+                })),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("flush", table = "Results", rows = 10).entered();
+            info!(code = 1004, "Writing {} rows failed", 10);
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["unit"], 12);
+        assert_eq!(line["type"], 201);
+        assert_eq!(line["simulation_time"], 1.5);
+        assert_eq!(line["iteration"], 3);
+        assert_eq!(line["fields"]["message"], "Writing 10 rows failed");
+        assert_eq!(line["fields"]["code"], 1004);
+        assert_eq!(
+            line["spans"],
+            serde_json::json!([{"name": "flush", "table": "Results", "rows": 10}])
+        );
+    }
+}
@@ -1,13 +1,21 @@
+use crate::parameter::LabelOptions;
+use crate::trnsys::error::TrnSysError;
+use crate::trnsys::json_log::{CallInfo, JsonFmt, SpanFieldsLayer};
 use crate::trnsys::{get_current_unit, log_message, messages, simulation_has_error, Severity};
 use std::backtrace;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter, Pointer};
-use std::fs::OpenOptions;
-use std::io::{Cursor, Write};
-use std::sync::{Arc, LazyLock, Mutex};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
+use strum_macros::{Display, EnumString};
+use tracing::callsite::Identifier;
 use tracing::field::{Field, Visit};
-use tracing::{error, Event, Level, Subscriber};
-use tracing_subscriber::filter::EnvFilter;
+use tracing::{error, info, Event, Level, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::writer::MakeWriterExt;
 use tracing_subscriber::fmt::{format, time, FormatEvent, FormatFields};
@@ -16,11 +24,127 @@ use tracing_subscriber::{
     fmt::{self, time::OffsetTime, writer::BoxMakeWriter},
     layer::SubscriberExt,
     registry::Registry,
-    Layer,
+    reload, Layer,
 };
 
-/// The threshold level for trnsys logging.
-const TRNSYS_LOG_LEVEL: Level = Level::INFO;
+/// Options configuring the log, given as labels or as environment variables.
+pub const LOG_OPTIONS: [&str; 6] = [
+    "LogLevel",
+    "LogFile",
+    "LogFormat",
+    "KeepLog",
+    "ForwardLevel",
+    "ForwardLimit",
+];
+
+/// Format of the log file, selectable by name, e.g. `LogFormat=Json`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum LogFormat {
+    /// Human-readable lines, prefixed with the unit number
+    #[default]
+    Text,
+    /// JSON lines, see [`JsonFmt`]
+    Json,
+}
+
+/// How the type logs. The first unit calling [`configure_logging`] decides for all units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    /// Level of the events written to the log file
+    pub level: LevelFilter,
+    /// The log file, relative to the deck directory. `None` for `<deck>_odbc.log`.
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+    /// Whether the log file is kept after a successful simulation
+    pub keep: bool,
+    /// Level of the events forwarded to TRNSYS
+    pub forward_level: LevelFilter,
+    /// Number of messages forwarded from the same place in the code, 0 for no limit
+    pub forward_limit: u32,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: LevelFilter::DEBUG,
+            file: None,
+            format: LogFormat::default(),
+            keep: false,
+            forward_level: LevelFilter::WARN,
+            forward_limit: 10,
+        }
+    }
+}
+
+impl LogSettings {
+    /// The settings given as labels, each falling back to its environment variable,
+    /// e.g. `TRNSYS_ODBC_LOG_LEVEL` for `LogLevel`.
+    pub fn from_options(options: &LabelOptions) -> Result<Self, TrnSysError> {
+        let mut options = options.clone();
+        for key in LOG_OPTIONS {
+            if options.get(key).is_none() {
+                if let Ok(value) = std::env::var(env_var_name(key)) {
+                    options.insert(key, &value);
+                }
+            }
+        }
+        let default = LogSettings::default();
+        Ok(LogSettings {
+            level: options.get_parsed("LogLevel")?.unwrap_or(default.level),
+            file: options.get("LogFile").map(PathBuf::from),
+            format: options.get_parsed("LogFormat")?.unwrap_or(default.format),
+            keep: options.get_bool("KeepLog")?.unwrap_or(default.keep),
+            forward_level: options
+                .get_parsed("ForwardLevel")?
+                .unwrap_or(default.forward_level),
+            forward_limit: options
+                .get_parsed("ForwardLimit")?
+                .unwrap_or(default.forward_limit),
+        })
+    }
+
+    /// The log file of a simulation of `deck`: `file` relative to the deck directory,
+    /// or `<deck>_odbc.log` next to the deck.
+    pub fn log_file(&self, deck: &Path) -> PathBuf {
+        let dir = deck.parent().unwrap_or(Path::new(""));
+        match &self.file {
+            Some(file) => dir.join(file),
+            None => dir.join(format!(
+                "{}_odbc.log",
+                deck.file_stem().unwrap_or_default().to_string_lossy()
+            )),
+        }
+    }
+}
+
+/// The environment variable of an option, e.g. `TRNSYS_ODBC_LOG_LEVEL` for `LogLevel`.
+fn env_var_name(key: &str) -> String {
+    let mut name = "TRNSYS_ODBC".to_string();
+    for c in key.chars() {
+        if c.is_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+static LOG_SETTINGS: LazyLock<RwLock<LogSettings>> =
+    LazyLock::new(|| RwLock::new(LogSettings::default()));
+
+/// Whether a unit has configured logging.
+static LOG_CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// Handle to change the level of the log file once the settings are known.
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Field of an event holding the error code passed to TRNSYS, e.g. `warn!(code = 1004, ...)`.
+pub const CODE_FIELD: &str = "code";
+
+/// Field marking an event as already reported to TRNSYS, so it only goes to the log file,
+/// e.g. `error!(reported = true, ...)`.
+pub const REPORTED_FIELD: &str = "reported";
 
 /// Custom function to handle trnsys logging.
 ///
@@ -65,6 +189,13 @@ impl MessageCollector {
             None
         }
     }
+
+    fn get_field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
 }
 
 impl Visit for MessageCollector {
@@ -81,15 +212,18 @@ impl Visit for MessageCollector {
 }
 
 /// Custom Layer to intercept high-priority events.
+/// Forwards the events at or above [`LogSettings::forward_level`], and at most
+/// [`LogSettings::forward_limit`] warnings and notices from the same place in the code.
 struct TrnSysLogLayer {
-    threshold: Level,
     formatter: format::Format,
+    /// Number of events forwarded per place in the code
+    forwarded: Mutex<HashMap<Identifier, u32>>,
 }
 
 impl TrnSysLogLayer {
-    pub fn new(threshold: Level) -> Self {
+    pub fn new() -> Self {
         TrnSysLogLayer {
-            threshold,
+            forwarded: Mutex::new(HashMap::new()),
             formatter: format::Format::default()
                 .with_line_number(true)
                 .with_file(true)
@@ -109,27 +243,58 @@ impl<S: Subscriber> Layer<S> for TrnSysLogLayer {
     /// * `_ctx` - The context of the subscriber.
     fn on_event(&self, event: &Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
         let metadata = event.metadata();
-        if metadata.level() <= &self.threshold {
-            let mut str = String::new();
-            let mut writer = tracing_subscriber::fmt::format::Writer::new(&mut str);
-
-            let mut collector = MessageCollector::new();
-            event.record(&mut collector);
-            let formatted_msg = collector.get_formatted_message();
-            if let Some(msg) = formatted_msg {
-                write!(
-                    writer,
-                    "{} {} {}: {}",
-                    metadata.level(),
-                    metadata.target(),
-                    metadata.name(),
-                    msg
-                )
-                .unwrap();
-            }
+        let (forward_level, forward_limit) = {
+            let settings = LOG_SETTINGS.read().unwrap();
+            (settings.forward_level, settings.forward_limit)
+        };
+        if forward_level < *metadata.level() {
+            return;
+        }
+        let mut str = String::new();
+        let mut writer = tracing_subscriber::fmt::format::Writer::new(&mut str);
+
+        let mut collector = MessageCollector::new();
+        event.record(&mut collector);
+        if collector.get_field(REPORTED_FIELD) == Some("true") {
+            return;
+        }
+        let error_code = collector
+            .get_field(CODE_FIELD)
+            .and_then(|code| code.parse().ok());
+        let formatted_msg = collector.get_formatted_message();
+        if let Some(msg) = formatted_msg {
+            write!(
+                writer,
+                "{} {} {}: {}",
+                metadata.level(),
+                metadata.target(),
+                metadata.name(),
+                msg
+            )
+            .unwrap();
+        }
 
-            log_in_trnsys(metadata.level().clone(), None, &str);
+        if *metadata.level() != Level::ERROR && forward_limit > 0 {
+            let mut forwarded = self.forwarded.lock().unwrap();
+            let count = forwarded.entry(metadata.callsite()).or_default();
+            *count += 1;
+            if *count > forward_limit + 1 {
+                return;
+            }
+            if *count == forward_limit + 1 {
+                log_in_trnsys(
+                    Level::INFO,
+                    None,
+                    &format!(
+                        "{} messages like this were forwarded, further ones only go to the log file: {}",
+                        forward_limit, str
+                    ),
+                );
+                return;
+            }
         }
+
+        log_in_trnsys(metadata.level().clone(), error_code, &str);
     }
 }
 
@@ -174,14 +339,75 @@ where
     }
 }
 
+/// Formats events as text or as JSON lines, as [`LogSettings::format`] says.
+struct LogFmt<F> {
+    text: UnitNoFmt<F>,
+    json: JsonFmt,
+}
+
+impl<S, N, F> FormatEvent<S, N> for LogFmt<F>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &tracing_subscriber::fmt::FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let format = LOG_SETTINGS.read().unwrap().format;
+        match format {
+            LogFormat::Text => self.text.format_event(ctx, writer, event),
+            LogFormat::Json => self.json.format_event(ctx, writer, event),
+        }
+    }
+}
+
 static LOGFILE_PATH: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
 
+/// The open log file, `None` once cleaned up.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// The path of the log file of the type, if tracing is initialized.
+pub fn log_file_path() -> Option<String> {
+    LOGFILE_PATH.lock().unwrap().clone()
+}
+
+/// Writes to the current log file, which moves next to the deck once logging is configured.
+struct LogFileWriter;
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The filter of the log, honouring the directives of the `RUST_LOG` environment variable.
+fn env_filter(level: LevelFilter) -> EnvFilter {
+    EnvFilter::from_default_env().add_directive(level.into())
+}
+
 /// Initializes tracing with custom layers and settings.
+/// Until a unit configures logging, the settings come from the environment variables
+/// and the log is written to a temporary file.
 ///
 /// # Arguments
 ///
 /// * `file_name` - The name of the log file to write to.
 pub fn init_tracing(file_name: Option<String>) {
+    let settings = LogSettings::from_options(&LabelOptions::default()).unwrap_or_default();
     let file_name = file_name.unwrap_or(get_default_log_file());
 
     // Store the log file path for later use
@@ -194,14 +420,15 @@ pub fn init_tracing(file_name: Option<String>) {
         .append(true)
         .open(file_name)
         .expect("Failed to open log file");
+    *LOG_FILE.lock().unwrap() = Some(log_file);
 
-    // Wrap the writer with a Mutex to ensure thread-safe writing
-    let file_writer = BoxMakeWriter::new(log_file);
+    let file_writer = BoxMakeWriter::new(|| LogFileWriter);
 
     let local_time = OffsetTime::local_rfc_3339().expect("Failed to get local time offset");
 
-    // Set up the filter (can be controlled via the RUST_LOG environment variable)
-    let filter = EnvFilter::from_default_env().add_directive("debug".parse().unwrap());
+    // Set up the filter, adjusted once a unit configures logging
+    let (filter, filter_handle) = reload::Layer::new(env_filter(settings.level));
+    let _ = FILTER_HANDLE.set(filter_handle);
 
     // Formatting Layer: output to both file and stdout
     let fmt_layer = fmt::layer()
@@ -210,14 +437,19 @@ pub fn init_tracing(file_name: Option<String>) {
         .with_line_number(true)
         .with_file(true)
         .with_ansi(false)
-        .event_format(UnitNoFmt(fmt::format()));
+        .event_format(LogFmt {
+            text: UnitNoFmt(fmt::format()),
+            json: JsonFmt::new(CallInfo::current),
+        });
 
-    let trnsys_log_layer = TrnSysLogLayer::new(TRNSYS_LOG_LEVEL);
+    let trnsys_log_layer = TrnSysLogLayer::new();
+    *LOG_SETTINGS.write().unwrap() = settings;
 
     // Combine layers
     let subscriber = Registry::default()
         .with(filter)
         .with(trnsys_log_layer)
+        .with(SpanFieldsLayer)
         .with(fmt_layer);
 
     // Global initialization
@@ -230,36 +462,108 @@ pub fn init_tracing(file_name: Option<String>) {
     }));
 }
 
+/// Applies the settings and moves the log file next to the `deck`, or where the settings say.
+/// Only the first unit configures logging, later units log with the same settings.
+pub fn configure_logging(settings: LogSettings, deck: &Path) -> Result<(), TrnSysError> {
+    if LOG_CONFIGURED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    if let Some(handle) = FILTER_HANDLE.get() {
+        handle
+            .reload(env_filter(settings.level))
+            .map_err(|e| TrnSysError::GeneralError(e.to_string()))?;
+    }
+    let path = settings.log_file(deck);
+    *LOG_SETTINGS.write().unwrap() = settings;
+    move_log_file(&path)?;
+    info!("Logging to {}", path.display());
+    Ok(())
+}
+
+/// Continues the log in a new file at `path`, starting with what has been logged so far.
+/// The file is overwritten, so it holds the log of the latest simulation.
+fn move_log_file(path: &Path) -> Result<(), TrnSysError> {
+    let mut log_file_path = LOGFILE_PATH.lock().unwrap();
+    let Some(old_path) = log_file_path.clone() else {
+        return Ok(());
+    };
+    if Path::new(&old_path) == path {
+        return Ok(());
+    }
+    let mut log_file = LOG_FILE.lock().unwrap();
+    let mut new_file = File::create(path)?;
+    new_file.write_all(&fs::read(&old_path)?)?;
+    *log_file = Some(new_file);
+    fs::remove_file(&old_path)?;
+    *log_file_path = Some(path.to_string_lossy().into_owned());
+    Ok(())
+}
+
 /// Cleans up the tracing system.
-/// Removes the log file if it exists.
-/// If any error stops the simulation, the log file will be moved to simulation folder instead.
+/// Removes the log file, unless an error stopped the simulation or the settings keep it.
 pub fn cleanup_tracing() {
     let mut log_file_path = LOGFILE_PATH.lock().unwrap();
 
-    if let Some(file_path) = log_file_path.as_ref() {
+    if let Some(file_path) = log_file_path.take() {
+        let keep = LOG_SETTINGS.read().unwrap().keep;
+        *LOG_FILE.lock().unwrap() = None;
+
         if simulation_has_error() {
-            // Move the log file to the current working directory
-            let new_file_path = std::env::current_dir()
-                .expect("Failed to get current directory")
-                .join("type_error.log");
-            let new_file_path_str = new_file_path.clone().to_str().unwrap().to_owned();
-            // remove if the file already exists
-            if new_file_path.exists() {
-                std::fs::remove_file(&new_file_path).expect("Failed to remove existing log file");
-            }
-            std::fs::copy(file_path, &new_file_path).expect("Failed to move log file");
             log_in_trnsys(
                 Level::INFO,
                 None,
                 &format!(
-                    "Simulation stopped due to error. Log file moved to current directory: {}",
-                    new_file_path_str
+                    "Simulation stopped due to error. See the log file: {}",
+                    file_path
                 ),
             );
+        } else if keep {
+            log_in_trnsys(Level::INFO, None, &format!("Log file: {}", file_path));
+        } else if let Err(e) = fs::remove_file(&file_path) {
+            log_in_trnsys(
+                Level::WARN,
+                None,
+                &format!("Failed to remove the log file {}: {}", file_path, e),
+            );
         }
+    }
+}
 
-        std::fs::remove_file(file_path).expect("Failed to remove log file");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_settings() {
+        let options = LabelOptions::parse(&[
+            "LogLevel=info".to_string(),
+            "LogFormat=json".to_string(),
+            "KeepLog=yes".to_string(),
+            "ForwardLevel=error".to_string(),
+            "LogFile=logs/run.log".to_string(),
+        ])
+        .unwrap();
+        let settings = LogSettings::from_options(&options).unwrap();
+        assert_eq!(settings.level, LevelFilter::INFO);
+        assert_eq!(settings.format, LogFormat::Json);
+        assert_eq!(settings.forward_level, LevelFilter::ERROR);
+        assert!(settings.keep);
+        assert_eq!(
+            settings.log_file(Path::new("/sim/office.dck")),
+            Path::new("/sim/logs/run.log")
+        );
+        assert_eq!(
+            LogSettings::default().log_file(Path::new("/sim/office.dck")),
+            Path::new("/sim/office_odbc.log")
+        );
+
+        let options = LabelOptions::parse(&["LogLevel=loud".to_string()]).unwrap();
+        assert!(LogSettings::from_options(&options).is_err());
+    }
 
-        *log_file_path = None;
+    #[test]
+    fn test_env_var_name() {
+        assert_eq!(env_var_name("LogLevel"), "TRNSYS_ODBC_LOG_LEVEL");
+        assert_eq!(env_var_name("ForwardLimit"), "TRNSYS_ODBC_FORWARD_LIMIT");
     }
 }
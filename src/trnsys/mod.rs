@@ -15,6 +15,7 @@ use util::c_bool;
 pub mod error;
 mod ext_c;
 pub(super) mod iteration_mode;
+mod json_log;
 pub mod logging;
 pub(super) mod param;
 mod state;
@@ -24,6 +25,7 @@ pub use state::*;
 
 // This file declares all the global functions available to C / C++ TRNSYS Types
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Severity {
     Notice,
     Warning,
@@ -232,7 +234,9 @@ pub fn set_numerical_derivative(mut i: i32, mut value: f64) {
     unsafe { ext_c::SETNUMERICALDERIVATIVE(&mut i, &mut value) }
 }
 
+/// Sets the unit of the output at the 0-based index `i`.
 pub fn set_output_units(mut i: i32, string: &str) {
+    i += 1;
     unsafe {
         let cstr = CString::new(string).unwrap();
         ext_c::SETOUTPUTUNITS(&mut i, cstr.as_ptr() as *mut c_char, string.len());
@@ -275,7 +279,7 @@ pub fn get_deck_filename() -> String {
             buffer.as_mut_slice().as_mut_ptr(),
             buffer.len(),
         );
-        CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        CStr::from_ptr(ptr).to_string_lossy().trim().to_string()
     }
 }
 
@@ -452,7 +456,7 @@ pub fn get_trnsys_input_file_dir() -> String {
             buffer.as_mut_slice().as_mut_ptr(),
             buffer.len(),
         );
-        CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        CStr::from_ptr(ptr).to_string_lossy().trim().to_string()
     }
 }
 
@@ -1,39 +1,263 @@
+use crate::alignment::{Alignment, PrintAligner};
+use crate::database::artifacts::{store_artifacts, Artifact};
 use crate::database::buffer::DataBuffer;
-use crate::database::column::MetaCol;
+use crate::database::column::{step_col_defs, MetaCol, STEP_COL};
 use crate::database::datatype::{ColDataType, ColDef};
+use crate::database::dialect::InsertMode;
+use crate::database::file_rollover::FileRollover;
 use crate::database::ms_access::MsAccessProvider;
 use crate::database::ms_excel::MsExcelProvider;
-use crate::database::odbc::{FileDbProvider, OdbcProvider, OdbcProviderImpl};
+use crate::database::odbc::{FileDbProvider, OdbcProvider};
 use crate::database::postgres::PostgreSQLProvider;
-use crate::database::sqlite::SqliteProvider;
-use crate::parameter::{DriverMode, Parameters};
+use crate::database::registry::ConnectionRegistry;
+use crate::database::retry::retry_count;
+use crate::database::secret::expand_placeholders;
+use crate::database::sqlite::{SqliteOptions, SqliteProvider};
+use crate::database::writer::{OdbcWriter, RecordWriter};
+use crate::database::xlsx::{WorkbookRegistry, XlsxWriter};
+use crate::error_policy::{ErrorPolicy, SpillFile};
+use crate::parameter::{DriverMode, LabelOptions, Parameters};
+use crate::report::SummaryReport;
+use crate::status::{RecorderStatus, StatusOutput};
 use crate::trnsys::error::TrnSysError;
+use crate::trnsys::logging::{configure_logging, log_file_path, LogSettings};
 use crate::trnsys::param::TrnSysValue;
 use crate::trnsys::*;
 use odbc_api::Environment;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
-use tracing::info;
+use std::time::Instant;
+use strum::IntoEnumIterator;
+use time::format_description::well_known::Rfc3339;
+use tracing::{debug_span, info, info_span, warn};
 use tracing_subscriber::fmt::format;
 
 static ENVIRONMENT: LazyLock<Environment> = LazyLock::new(|| Environment::new().unwrap());
 
+/// Connections shared by all units of this type in the process.
+static CONNECTIONS: LazyLock<ConnectionRegistry> =
+    LazyLock::new(|| ConnectionRegistry::new(&ENVIRONMENT));
+
+/// Workbooks shared by all units writing xlsx files natively.
+static WORKBOOKS: LazyLock<WorkbookRegistry> = LazyLock::new(WorkbookRegistry::new);
+
+/// Connects to the database file of a file driver mode.
+fn connect_file(
+    driver_mode: DriverMode,
+    db_path: &str,
+    sqlite: &SqliteOptions,
+) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> {
+    let provider: Box<dyn OdbcProvider> = match driver_mode {
+        DriverMode::MsAccessFile => {
+            let mut db_provider = MsAccessProvider::new();
+            db_provider.setup_by_path(&CONNECTIONS, db_path, None)?;
+            Box::new(db_provider)
+        }
+        DriverMode::MsExcelFile => {
+            let mut db_provider = MsExcelProvider::new();
+            db_provider.setup_by_path(&CONNECTIONS, db_path, None)?;
+            Box::new(db_provider)
+        }
+        DriverMode::SqliteFile => {
+            let mut db_provider = SqliteProvider::new().with_options(sqlite.clone());
+            db_provider.setup_by_path(&CONNECTIONS, db_path, None)?;
+            Box::new(db_provider)
+        }
+        mode => {
+            return Err(TrnSysError::GeneralError(format!(
+                "Driver mode {:?} has no database file.",
+                mode
+            )))
+        }
+    };
+    Ok(provider)
+}
+
+/// Overrides whether the driver gets the rows as parameter arrays.
+fn set_param_arrays(provider: &mut dyn OdbcProvider<'static>, param_arrays: Option<bool>) {
+    if let Some(param_arrays) = param_arrays {
+        let mut capabilities = provider.get_capabilities();
+        capabilities.bulk_params = param_arrays;
+        provider.set_capabilities(capabilities);
+    }
+}
+
+/// The path of the database or workbook file, with placeholders expanded.
+/// `None` for connection strings and DSNs, which the connection registry expands when connecting.
+fn file_path(params: &Parameters) -> Result<Option<String>, TrnSysError> {
+    match params.driver_mode {
+        DriverMode::MsAccessFile
+        | DriverMode::MsExcelFile
+        | DriverMode::SqliteFile
+        | DriverMode::XlsxFile => expand_placeholders(params.connection_string.expose()).map(Some),
+        DriverMode::ConnectionString | DriverMode::Dsn | DriverMode::Postgres => Ok(None),
+    }
+}
+
+/// Connects to the database of an ODBC driver mode, to the file at `db_path` for file modes,
+/// see [`file_path`].
+fn connect(
+    params: &Parameters,
+    db_path: Option<&str>,
+) -> Result<Box<dyn OdbcProvider<'static>>, TrnSysError> {
+    let mut provider: Box<dyn OdbcProvider> = match params.driver_mode {
+        DriverMode::ConnectionString => {
+            let connection =
+                CONNECTIONS.connect_by_conn_str(params.connection_string.expose(), None)?;
+            params.dialect.connect(connection)?
+        }
+        DriverMode::MsAccessFile | DriverMode::MsExcelFile | DriverMode::SqliteFile => {
+            let db_path = db_path.ok_or_else(|| {
+                TrnSysError::GeneralError("No database file to connect to.".to_string())
+            })?;
+            connect_file(params.driver_mode, db_path, &params.sqlite)?
+        }
+        DriverMode::Dsn => {
+            let connection = CONNECTIONS.connect_by_dsn(
+                params.connection_string.expose(),
+                &params.user,
+                params.password.expose(),
+                None,
+            )?;
+            params.dialect.connect(connection)?
+        }
+        DriverMode::Postgres => {
+            let mut db_provider = PostgreSQLProvider::new();
+            db_provider.setup_by_conn_str(&CONNECTIONS, params.connection_string.expose(), None)?;
+            Box::new(db_provider)
+        }
+        DriverMode::XlsxFile => {
+            return Err(TrnSysError::GeneralError(
+                "Xlsx files are written without ODBC.".to_string(),
+            ))
+        }
+    };
+    set_param_arrays(provider.as_mut(), params.param_arrays);
+    Ok(provider)
+}
+
+/// Creates the writer of the rows of a table with the columns `cols`, besides the meta columns.
+fn create_writer(
+    params: &Parameters,
+    table_name: &str,
+    cols: Vec<ColDef>,
+    insert_mode: InsertMode,
+) -> Result<Box<dyn RecordWriter>, TrnSysError> {
+    let db_path = file_path(params)?;
+    if let (DriverMode::XlsxFile, Some(path)) = (params.driver_mode, db_path.as_deref()) {
+        let workbook = WORKBOOKS.open(path)?;
+        let units = params
+            .input_names
+            .iter()
+            .cloned()
+            .zip(params.input_units.iter().cloned())
+            .collect();
+        Ok(Box::new(XlsxWriter::new(
+            workbook,
+            table_name,
+            &params.variant_name,
+            units,
+            params.number_format.clone(),
+            params.max_rows,
+        )))
+    } else {
+        let provider = connect(params, db_path.as_deref())?;
+
+        // The stricter of the configured limit and the limit of the target
+        let max_rows = match (params.max_rows, provider.get_capabilities().max_rows) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let max_file_size = match (
+            params.max_file_size,
+            provider.get_capabilities().max_file_size,
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut writer =
+            OdbcWriter::new(provider, table_name, &params.variant_name, cols, max_rows)
+                .with_indexes(params.indexes.clone())
+                .with_insert_mode(insert_mode);
+        if let Some(db_path) = db_path.as_deref() {
+            writer = writer.with_schema_lock(db_path)?;
+        }
+        if let (Some(db_path), Some(max_file_size)) = (db_path.as_deref(), max_file_size) {
+            let driver_mode = params.driver_mode;
+            let sqlite = params.sqlite.clone();
+            let param_arrays = params.param_arrays;
+            writer = writer.with_file_rollover(FileRollover::new(
+                db_path,
+                max_file_size,
+                Box::new(move |path| {
+                    let mut provider = connect_file(driver_mode, path, &sqlite)?;
+                    set_param_arrays(provider.as_mut(), param_arrays);
+                    Ok(provider)
+                }),
+            )?);
+        }
+        Ok(Box::new(writer))
+    }
+}
+
 pub(crate) struct TrnSysType {
     parameters: Option<Parameters>,
-    db_provider: Option<Box<dyn OdbcProvider<'static>>>,
+    writer: Option<Box<dyn RecordWriter>>,
     last_recorded_no: u32,
     buffer: Vec<DataBuffer>,
+    /// Writer of the iterations table, if iterations are recorded
+    iteration_writer: Option<Box<dyn RecordWriter>>,
+    /// The iterations of the current timestep
+    timestep_iterations: Vec<DataBuffer>,
+    /// The recorded iterations not written yet
+    iteration_buffer: Vec<DataBuffer>,
+    /// Interpolates the rows at the exact print times with `Alignment=Exact`
+    aligner: Option<PrintAligner>,
+    /// Shown on the outputs
+    status: RecorderStatus,
+    /// Entries in the Simulation Summary Report, if the unit is included in it
+    report: Option<SummaryReport>,
+    /// Number of buffered rows triggering the next flush, raised after a failed flush
+    flush_at: usize,
+    iteration_flush_at: usize,
+    /// Receive the rows that cannot be written with `OnError=Spill`
+    spill: Option<SpillFile>,
+    iteration_spill: Option<SpillFile>,
 }
 
 const ROW_BUFFER_SIZE: usize = 24 * 30;
 
+/// Rows kept buffered after failed flushes with `OnError=Warn` before giving up.
+const MAX_PENDING_ROWS: usize = 100 * ROW_BUFFER_SIZE;
+
+/// Print times within this share of the minimum timestep from a timestep take its values.
+const ALIGNMENT_TOLERANCE: f64 = 1e-3;
+
+/// Logical unit of the listing file.
+const LISTING_LU: i32 = 6;
+
+/// Column of the iterations table holding the iteration of the timestep.
+const ITERATION_COL: &str = "Iteration";
+
 impl TrnSysType {
     /// set up parameters for the TRNSYS type
     pub fn new() -> Self {
         TrnSysType {
             parameters: None,
-            db_provider: None,
+            writer: None,
             last_recorded_no: 0,
             buffer: Vec::with_capacity(ROW_BUFFER_SIZE),
+            iteration_writer: None,
+            timestep_iterations: vec![],
+            iteration_buffer: vec![],
+            aligner: None,
+            status: RecorderStatus::default(),
+            report: None,
+            flush_at: ROW_BUFFER_SIZE - 10,
+            iteration_flush_at: ROW_BUFFER_SIZE,
+            spill: None,
+            iteration_spill: None,
         }
     }
 
@@ -43,7 +267,28 @@ impl TrnSysType {
     /// If inconsistent, error will be automatically raised.
     pub fn first_call_of_simulation(&mut self, state: &mut TrnSysState) -> Result<(), TrnSysError> {
         // All the "Very First Call of the Simulation Manipulations"
+        let max_outputs = StatusOutput::iter().count();
+        if state.num_outputs as usize > max_outputs {
+            return Err(TrnSysError::GeneralError(format!(
+                "At most {} status outputs are available, got {}.",
+                max_outputs, state.num_outputs
+            )));
+        }
+        for (i, output) in StatusOutput::iter()
+            .take(state.num_outputs as usize)
+            .enumerate()
+        {
+            set_output_units(i as i32, output.unit());
+        }
 
+        let labels = (1..=state.num_labels)
+            .map(|i| get_label(get_current_unit(), i))
+            .collect::<Vec<_>>();
+        let options = LabelOptions::after_inputs(&labels, state.num_inputs.max(0) as usize)?;
+        configure_logging(LogSettings::from_options(&options)?, &deck_path())?;
+        if is_included_in_ssr() {
+            SummaryReport::declare(&options);
+        }
         Ok(())
     }
 
@@ -58,60 +303,86 @@ impl TrnSysType {
     /// This function is called at the beginning of each simulation.
     /// Do start calculations here and store the results in the static store
     pub fn simulation_starts(&mut self, state: &mut TrnSysState) -> Result<(), TrnSysError> {
-        info!("Simulation Starts. Connecting to Database...");
         let params = self.parameters.as_ref().unwrap();
-        let provider: Box<dyn OdbcProvider> = match params.driver_mode {
-            DriverMode::ConnectionString => {
-                let mut db_provider = OdbcProviderImpl::new();
-                db_provider.setup_by_conn_str(
-                    &ENVIRONMENT,
-                    params.connection_string.as_str(),
-                    None,
-                )?;
-                Box::new(db_provider)
-            }
-            DriverMode::MsAccessFile => {
-                let mut db_provider = MsAccessProvider::new();
-                db_provider.setup_by_path(&ENVIRONMENT, params.connection_string.as_str(), None)?;
-                Box::new(db_provider)
-            }
-            DriverMode::MsExcelFile => {
-                let mut db_provider = MsExcelProvider::new();
-                db_provider.setup_by_path(&ENVIRONMENT, params.connection_string.as_str(), None)?;
-                Box::new(db_provider)
-            }
-            DriverMode::SqliteFile => {
-                let mut db_provider = SqliteProvider::new();
-                db_provider.setup_by_path(&ENVIRONMENT, params.connection_string.as_str(), None)?;
-                Box::new(db_provider)
-            }
-            DriverMode::Postgres => {
-                let mut db_provider = PostgreSQLProvider::new();
-                db_provider.setup_by_conn_str(
-                    &ENVIRONMENT,
-                    params.connection_string.as_str(),
-                    None,
-                )?;
-                Box::new(db_provider)
-            }
-        };
+        let _span = info_span!(
+            "simulation_starts",
+            table = %params.table_name,
+            variant = %params.variant_name
+        )
+        .entered();
+        info!("Simulation Starts. Connecting to Database...");
 
-        // Format the fields
-        let input_names = params
+        let input_cols = params
             .input_names
             .iter()
             .map(|s| ColDef::new(s, ColDataType::Number { decimal: true }, false, false))
             .collect::<Vec<_>>();
+        let cols = match params.step_key {
+            true => step_col_defs()
+                .into_iter()
+                .chain(input_cols.clone())
+                .collect(),
+            false => input_cols.clone(),
+        };
+        let mut writer = create_writer(params, &params.table_name, cols, params.insert_mode)?;
 
-        self.db_provider = Some(provider);
+        // Ensure the table and remove existing variant data
+        let started = Instant::now();
+        writer.start()?;
+        self.status.database_time += started.elapsed();
+        self.writer = Some(writer);
 
-        let db = self.db_provider.as_mut().unwrap();
+        self.aligner = (params.alignment == Alignment::Exact).then(|| {
+            PrintAligner::new(
+                get_simulation_start_time(),
+                params.print_interval,
+                get_minimum_timestep() * ALIGNMENT_TOLERANCE,
+            )
+        });
 
-        db.ensure_table(&params.table_name, input_names, None)?;
+        if is_included_in_ssr() {
+            self.report = Some(SummaryReport::init(params));
+        }
 
-        // Remove existing variant data
-        db.remove_variant(&params.table_name, &params.variant_name)?;
+        if params.on_error == ErrorPolicy::Spill {
+            let dir = PathBuf::from(get_trnsys_input_file_dir());
+            let spill = SpillFile::new(&dir, &params.table_name, &params.variant_name);
+            // the rows of the variant were removed from the database as well
+            if spill.path().exists() {
+                info!(
+                    "Removing the spill file {} of an earlier run",
+                    spill.path().display()
+                );
+                fs::remove_file(spill.path())?;
+            }
+            self.spill = Some(spill);
+        }
 
+        if params.iterations.is_some() {
+            // the iterations of a timestep share its SimTime, so the iteration is part of the key
+            let cols = std::iter::once(ColDef::new(
+                ITERATION_COL,
+                ColDataType::Number { decimal: false },
+                true,
+                true,
+            ))
+            .chain(input_cols)
+            .collect();
+            let table_name = format!("{}_iterations", params.table_name);
+            if let Some(spill) = self.spill.as_ref() {
+                let dir = spill.path().parent().unwrap_or(Path::new("."));
+                let spill = SpillFile::new(dir, &table_name, &params.variant_name);
+                if spill.path().exists() {
+                    fs::remove_file(spill.path())?;
+                }
+                self.iteration_spill = Some(spill);
+            }
+            let mut writer = create_writer(params, &table_name, cols, InsertMode::Insert)?;
+            let started = Instant::now();
+            writer.start()?;
+            self.status.database_time += started.elapsed();
+            self.iteration_writer = Some(writer);
+        }
         Ok(())
     }
 
@@ -120,8 +391,22 @@ impl TrnSysType {
     pub fn simulation_ends(&mut self, state: &mut TrnSysState) -> Result<(), TrnSysError> {
         // Do all of the Last Call Manipulations Here
         info!("Simulation Ends");
-        self.write_buffer()?;
-        self.db_provider = None;
+        self.write_buffer(true)?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.finish()?;
+        }
+        self.writer = None;
+        if self.parameters.as_ref().unwrap().store_artifacts {
+            self.store_artifacts()?;
+        }
+
+        // the simulation may stop in a timestep that did not converge
+        self.end_timestep_iterations();
+        self.write_iteration_buffer(true)?;
+        if let Some(writer) = self.iteration_writer.as_mut() {
+            writer.finish()?;
+        }
+        self.iteration_writer = None;
         Ok(())
     }
 
@@ -130,7 +415,26 @@ impl TrnSysType {
     /// This function should return the values of the outputs for the current time step. \
     /// TrnSys will take care of the convergence of the simulation.
     pub fn iterate(&mut self, state: &mut TrnSysState) -> Result<Vec<TrnSysValue>, TrnSysError> {
-        Ok(vec![])
+        let params = self.parameters.as_ref().unwrap();
+        if let Some(recording) = params.iterations.as_ref() {
+            if recording.includes(state.simulation_time) {
+                let iteration = get_timestep_iteration();
+                let row = std::iter::once(iteration as f64)
+                    .chain(state.inputs.iter().map(|v| v.value))
+                    .collect::<Vec<f64>>();
+                let mut buffer_row = DataBuffer::new(Some(row));
+                buffer_row.insert_meta_col(MetaCol::SimulationTime, state.simulation_time);
+                buffer_row.insert_meta_col(MetaCol::Variant, params.variant_name.clone());
+                self.timestep_iterations.push(buffer_row);
+            }
+        }
+        self.status.rows_buffered = self.buffer.len();
+        Ok(self
+            .status
+            .values(state.num_outputs as usize)
+            .into_iter()
+            .map(TrnSysValue::new)
+            .collect())
     }
 
     /// At the end of each time step, each Type in a simulation is recalled.
@@ -138,25 +442,57 @@ impl TrnSysType {
     /// in the dynamic storage
     pub fn end_of_timestep(&mut self, state: &mut TrnSysState) -> Result<(), TrnSysError> {
         // Perform Any "End of Timestep" Manipulations That May Be Required
-        if self.get_new_record_no(state.simulation_time) == self.last_recorded_no {
-            return Ok(());
-        } else {
-            self.last_recorded_no = self.get_new_record_no(state.simulation_time);
+        let values = state.inputs.iter().map(|v| v.value).collect::<Vec<f64>>();
+        if let Some(report) = self.report.as_ref() {
+            report.update(&values);
         }
 
-        // Insert data
-        let row = state.inputs.iter().map(|v| v.value).collect::<Vec<f64>>();
-        let mut buffer_row = DataBuffer::new(Some(row));
+        if self.iteration_writer.is_some() {
+            self.end_timestep_iterations();
+            if self.iteration_buffer.len() >= self.iteration_flush_at
+                || get_simulation_stop_time() == state.simulation_time
+            {
+                self.write_iteration_buffer(false)?;
+            }
+        }
+
+        let interval = self.parameters.as_ref().unwrap().print_interval;
+        let rows = match self.aligner.as_mut() {
+            Some(aligner) => aligner
+                .rows(state.simulation_time, values)
+                .into_iter()
+                // the print times are multiples of the interval up to rounding errors
+                .map(|(time, row)| ((time / interval).round() as u32, time, row))
+                .collect(),
+            None => {
+                if self.get_new_record_no(state.simulation_time) == self.last_recorded_no {
+                    return Ok(());
+                }
+                self.last_recorded_no = self.get_new_record_no(state.simulation_time);
+                vec![(self.last_recorded_no, get_simulation_time(), values)]
+            }
+        };
+        if rows.is_empty() {
+            return Ok(());
+        }
 
-        // Insert meta columns
         let params = self.parameters.as_ref().unwrap();
-        buffer_row.insert_meta_col(MetaCol::SimulationTime, get_simulation_time());
-        buffer_row.insert_meta_col(MetaCol::Variant, params.variant_name.clone());
-        self.buffer.push(buffer_row);
+        for (record_no, simulation_time, row) in rows {
+            // Insert data, led by the step if it is part of the key
+            let row = match params.step_key {
+                true => std::iter::once(record_no as f64).chain(row).collect(),
+                false => row,
+            };
+            let mut buffer_row = DataBuffer::new(Some(row));
+
+            // Insert meta columns
+            buffer_row.insert_meta_col(MetaCol::SimulationTime, simulation_time);
+            buffer_row.insert_meta_col(MetaCol::Variant, params.variant_name.clone());
+            self.buffer.push(buffer_row);
+        }
 
         if self.is_time_to_write_buffer(state.simulation_time) {
-            self.write_buffer()?;
-            self.buffer.clear();
+            self.write_buffer(false)?;
         }
         Ok(())
     }
@@ -179,35 +515,222 @@ impl TrnSysType {
     }
 
     fn is_time_to_write_buffer(&self, simulation_time: f64) -> bool {
-        self.buffer.len() >= ROW_BUFFER_SIZE - 10 || get_simulation_stop_time() == simulation_time
+        self.buffer.len() >= self.flush_at || get_simulation_stop_time() == simulation_time
     }
 
-    fn write_buffer(&mut self) -> Result<(), TrnSysError> {
+    /// Writes the buffered rows, applying the error policy if that fails.
+    /// The `last` flush of the simulation keeps no rows for later.
+    fn write_buffer(&mut self, last: bool) -> Result<(), TrnSysError> {
         if self.buffer.len() == 0 {
             return Ok(());
         }
-        let db_provider = self.db_provider.as_mut().unwrap();
+        let writer = self.writer.as_mut().unwrap();
         let params = self.parameters.as_ref().unwrap();
-
-        let col_names = self
-            .buffer
-            .iter()
-            .map(|r| r.get_col_names(params.input_names.clone()))
+        let input_names = params
+            .step_key
+            .then(|| STEP_COL.to_string())
+            .into_iter()
+            .chain(params.input_names.iter().cloned())
             .collect::<Vec<_>>();
+        let _span =
+            debug_span!("flush", table = %params.table_name, rows = self.buffer.len()).entered();
+        let (num_rows, retries, started) = (self.buffer.len(), retry_count(), Instant::now());
+        let result = write_rows(writer.as_mut(), &mut self.buffer, &input_names);
+        self.status.record_flush(
+            num_rows,
+            started.elapsed(),
+            retry_count() > retries,
+            result.is_ok(),
+        );
+        if let Some(report) = self.report.as_ref() {
+            report.update_rows_written(self.status.rows_written);
+        }
+        match result {
+            Ok(()) => self.flush_at = ROW_BUFFER_SIZE - 10,
+            Err(e) => {
+                handle_write_error(
+                    e,
+                    params.on_error,
+                    self.spill.as_ref(),
+                    &mut self.buffer,
+                    &input_names,
+                    last,
+                )?;
+                // wait for another buffer of rows before retrying
+                self.flush_at = self.buffer.len() + ROW_BUFFER_SIZE;
+            }
+        }
+        Ok(())
+    }
 
-        let row_insertables = self
-            .buffer
-            .drain(..)
-            .map(|row| row.into_insertable())
-            .collect::<Vec<_>>();
+    /// Stores the deck, the listing and the log file, as written so far, in the database.
+    fn store_artifacts(&self) -> Result<(), TrnSysError> {
+        let params = self.parameters.as_ref().unwrap();
+        if matches!(
+            params.driver_mode,
+            DriverMode::XlsxFile | DriverMode::MsExcelFile
+        ) {
+            warn!("Artifacts cannot be stored in Excel files.");
+            return Ok(());
+        }
 
-        db_provider.batch_insert_data(
-            &params.table_name,
-            col_names.first().unwrap().clone(),
-            row_insertables,
-        )?;
+        let files = [
+            ("Deck", Some(deck_path())),
+            ("Listing", Some(PathBuf::from(get_lu_filename(LISTING_LU)))),
+            ("Log", log_file_path().map(PathBuf::from)),
+        ];
+        let mut artifacts = vec![];
+        for (kind, path) in files {
+            let Some(path) = path else {
+                continue;
+            };
+            match fs::read(&path) {
+                Ok(content) => artifacts.push(Artifact {
+                    kind: kind.to_string(),
+                    file_name: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    content,
+                }),
+                Err(e) => warn!("Cannot read the {} {}: {}", kind, path.display(), e),
+            }
+        }
+
+        let run_start = params
+            .run_start
+            .format(&Rfc3339)
+            .map_err(|e| TrnSysError::GeneralError(e.to_string()))?;
+        let provider = connect(params, file_path(params)?.as_deref())?;
+        store_artifacts(
+            provider.as_ref(),
+            &params.variant_name,
+            &run_start,
+            artifacts,
+        )
+    }
 
-        self.buffer.clear();
+    /// Keeps the iterations of the finished timestep if it took more iterations than the threshold.
+    fn end_timestep_iterations(&mut self) {
+        let Some(recording) = self.parameters.as_ref().unwrap().iterations.as_ref() else {
+            return;
+        };
+        if self.timestep_iterations.len() > recording.min_iterations as usize {
+            self.iteration_buffer.append(&mut self.timestep_iterations);
+        } else {
+            self.timestep_iterations.clear();
+        }
+    }
+
+    fn write_iteration_buffer(&mut self, last: bool) -> Result<(), TrnSysError> {
+        let Some(writer) = self.iteration_writer.as_mut() else {
+            return Ok(());
+        };
+        if self.iteration_buffer.is_empty() {
+            return Ok(());
+        }
+        let params = self.parameters.as_ref().unwrap();
+        let input_names = std::iter::once(ITERATION_COL.to_string())
+            .chain(params.input_names.iter().cloned())
+            .collect::<Vec<_>>();
+        let _span = debug_span!(
+            "flush_iterations",
+            table = %params.table_name,
+            rows = self.iteration_buffer.len()
+        )
+        .entered();
+        let started = Instant::now();
+        let result = write_rows(writer.as_mut(), &mut self.iteration_buffer, &input_names);
+        self.status.database_time += started.elapsed();
+        match result {
+            Ok(()) => self.iteration_flush_at = ROW_BUFFER_SIZE,
+            Err(e) => {
+                handle_write_error(
+                    e,
+                    params.on_error,
+                    self.iteration_spill.as_ref(),
+                    &mut self.iteration_buffer,
+                    &input_names,
+                    last,
+                )?;
+                self.iteration_flush_at = self.iteration_buffer.len() + ROW_BUFFER_SIZE;
+            }
+        }
         Ok(())
     }
 }
+
+/// The path of the deck file, relative ones resolved against the input file directory.
+fn deck_path() -> PathBuf {
+    let deck = PathBuf::from(get_deck_filename());
+    match deck.is_relative() {
+        true => PathBuf::from(get_trnsys_input_file_dir()).join(deck),
+        false => deck,
+    }
+}
+
+/// Writes the buffered rows, whose inputs are named `input_names`,
+/// and empties the buffer if that succeeded.
+fn write_rows(
+    writer: &mut dyn RecordWriter,
+    buffer: &mut Vec<DataBuffer>,
+    input_names: &[String],
+) -> Result<(), TrnSysError> {
+    let Some(first) = buffer.first() else {
+        return Ok(());
+    };
+    let col_names = first.get_col_names(input_names.to_vec());
+    let rows = buffer
+        .iter()
+        .map(|row| row.clone().into_values())
+        .collect::<Vec<_>>();
+    writer.write(col_names, rows)?;
+    buffer.clear();
+    Ok(())
+}
+
+/// Applies the error policy to the rows left in the buffer by a failed write.
+/// Returns the error if the simulation has to stop.
+fn handle_write_error(
+    error: TrnSysError,
+    policy: ErrorPolicy,
+    spill: Option<&SpillFile>,
+    buffer: &mut Vec<DataBuffer>,
+    input_names: &[String],
+    last: bool,
+) -> Result<(), TrnSysError> {
+    if error.severity(policy, last) != Severity::Warning {
+        return Err(error);
+    }
+    match (policy, spill) {
+        (ErrorPolicy::Warn, _) if buffer.len() < MAX_PENDING_ROWS => {
+            warn!(
+                code = error.code(),
+                "Writing {} rows failed, keeping them for the next flush: {}",
+                buffer.len(),
+                error.trnsys_message()
+            );
+            Ok(())
+        }
+        (ErrorPolicy::Spill, Some(spill)) => {
+            let Some(first) = buffer.first() else {
+                return Ok(());
+            };
+            let col_names = first.get_col_names(input_names.to_vec());
+            let rows = buffer
+                .drain(..)
+                .map(|row| row.into_values())
+                .collect::<Vec<_>>();
+            spill.append(&col_names, &rows)?;
+            warn!(
+                code = error.code(),
+                "Writing {} rows failed, appended them to {}: {}",
+                rows.len(),
+                spill.path().display(),
+                error.trnsys_message()
+            );
+            Ok(())
+        }
+        _ => Err(error),
+    }
+}
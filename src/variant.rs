@@ -0,0 +1,160 @@
+use crate::trnsys::error::TrnSysError;
+use time::format_description::parse_strftime_borrowed;
+use time::OffsetDateTime;
+
+/// Format of `{date}` without a format.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// What the placeholders of a variant name template refer to.
+pub struct VariantContext<'a> {
+    /// Path of the deck file
+    pub deck: &'a str,
+    /// Unit number of the recorder
+    pub unit: i32,
+    /// Values of the variant parameters, the optional parameters after the three of the recorder
+    pub params: &'a [f64],
+    /// Wall-clock time of the simulation start
+    pub now: OffsetDateTime,
+}
+
+/// Expands the placeholders of the variant name, so every parametric run gets its own variant:
+///
+/// * `{deck}` is the name of the deck file without directory and extension.
+/// * `{unit}` is the unit number of the recorder.
+/// * `{param:N}` is the value of the N-th variant parameter, counting from 1. The deck usually
+///   sets these optional parameters to the constants a parametric run varies.
+/// * `{env:NAME}` is the environment variable `NAME`.
+/// * `{date}` or `{date:FORMAT}` is the wall-clock time, formatted like `strftime`, e.g. `%Y%m%d`.
+/// * `{{` and `}}` are literal braces.
+pub fn expand_variant(template: &str, context: &VariantContext) -> Result<String, TrnSysError> {
+    let error = |message: String| TrnSysError::ConversionError {
+        param: "Variant Name".to_string(),
+        message,
+    };
+
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        result.push_str(&rest[..start]);
+        if rest[start..].starts_with("{{") || rest[start..].starts_with("}}") {
+            result.push_str(&rest[start..start + 1]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        if rest[start..].starts_with('}') {
+            return Err(error(format!("Unmatched '}}' in '{}'.", template)));
+        }
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| error(format!("Unclosed '{{' in '{}'.", template)))?;
+        let placeholder = &rest[start + 1..end];
+        let (name, arg) = match placeholder.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg)),
+            None => (placeholder.trim(), None),
+        };
+        let value = match (name, arg) {
+            ("deck", None) => deck_name(context.deck).to_string(),
+            ("unit", None) => context.unit.to_string(),
+            ("param", Some(no)) => no
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|no| no.checked_sub(1))
+                .and_then(|i| context.params.get(i))
+                .map(|value| format_number(*value))
+                .ok_or_else(|| {
+                    error(format!(
+                        "No variant parameter {} in '{{{}}}', {} given after the 3 parameters.",
+                        no.trim(),
+                        placeholder,
+                        context.params.len()
+                    ))
+                })?,
+            ("env", Some(var)) => std::env::var(var.trim())
+                .map_err(|_| error(format!("Environment variable '{}' is not set.", var.trim())))?,
+            ("date", format) => {
+                let format = parse_strftime_borrowed(format.unwrap_or(DEFAULT_DATE_FORMAT))
+                    .map_err(|e| {
+                        error(format!(
+                            "Invalid date format in '{{{}}}': {}",
+                            placeholder, e
+                        ))
+                    })?;
+                context
+                    .now
+                    .format(&format)
+                    .map_err(|e| error(format!("Cannot format '{{{}}}': {}", placeholder, e)))?
+            }
+            _ => return Err(error(format!("Unknown placeholder '{{{}}}'.", placeholder))),
+        };
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The file name without directory and extension, for Windows and Unix paths alike.
+fn deck_name(path: &str) -> &str {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem)
+}
+
+/// Whole numbers without decimals, e.g. `3` for a parameter value of 3.0.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.0}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn context() -> VariantContext<'static> {
+        VariantContext {
+            deck: "C:\\Projects\\Office.dck",
+            unit: 12,
+            // e.g. the timestep and the insulation thickness varied by a parametric run
+            params: &[0.5, 3.0],
+            now: datetime!(2024-03-05 14:30:00 UTC),
+        }
+    }
+
+    #[test]
+    fn test_expand_variant() {
+        assert_eq!(
+            expand_variant("{deck}_{param:2}_{date:%Y%m%d}_{unit}", &context()).unwrap(),
+            "Office_3_20240305_12"
+        );
+        assert_eq!(
+            expand_variant("Dt{param:1} {date}", &context()).unwrap(),
+            "Dt0.5 20240305_143000"
+        );
+        assert_eq!(expand_variant("{{Base}}", &context()).unwrap(), "{Base}");
+        assert_eq!(expand_variant("Base", &context()).unwrap(), "Base");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in [
+            "{param:3}",
+            "{foo}",
+            "{deck",
+            "deck}",
+            "{env:TRNSYS_ODBC_UNSET}",
+        ] {
+            assert!(
+                expand_variant(template, &context()).is_err(),
+                "{}",
+                template
+            );
+        }
+    }
+}